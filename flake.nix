@@ -46,7 +46,7 @@
           src = ./.;
           inherit nativeBuildInputs;
           inherit buildInputs;
-          cargoBuildOptions = x: x ++ [ "--no-default-features" ];
+          cargoBuildOptions = x: x ++ [ "--no-default-features" "--features game" ];
         };
         sneakysnakes = pkgs.stdenv.mkDerivation {
           pname = "sneakysnakes";
@@ -80,6 +80,7 @@
           inherit buildInputs;
           cargoBuildOptions = x: x ++ [
             "--no-default-features"
+            "--features game"
             "--target wasm32-unknown-unknown"
           ];
         };
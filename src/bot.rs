@@ -0,0 +1,179 @@
+use crate::items::{EffectKind, ItemDefinition, ItemTarget};
+use crate::rules::Steering;
+use crate::simulation::{Player, Simulation, SteerInput};
+use bevy_math::{Quat, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    /// How far ahead the bot looks for obstacles, in game units at base
+    /// speed.
+    fn look_ahead(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.12,
+            Difficulty::Medium => 0.25,
+            Difficulty::Hard => 0.45,
+        }
+    }
+
+    /// Time between seeing something and reacting to it.
+    fn reaction_time(&self) -> Duration {
+        match self {
+            Difficulty::Easy => Duration::from_millis(250),
+            Difficulty::Medium => Duration::from_millis(120),
+            Difficulty::Hard => Duration::from_millis(30),
+        }
+    }
+
+    pub fn get_text(&self) -> String {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+        }
+        .to_string()
+    }
+}
+
+/// Ray directions relative to the heading, from hard right to hard left.
+const RAY_ANGLES_DEG: [f32; 7] = [-90., -60., -30., 0., 30., 60., 90.];
+
+/// Computer player steering by casting rays over the arena bitmap.
+pub struct Bot {
+    pub difficulty: Difficulty,
+    /// Decisions waiting for the reaction delay to pass, oldest first.
+    pending: VecDeque<SteerInput>,
+}
+
+impl Bot {
+    pub fn new(difficulty: Difficulty) -> Self {
+        Bot {
+            difficulty,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Input for player `index` in the next tick of length `delta`.
+    pub fn steer(&mut self, simulation: &Simulation, index: usize, delta: Duration) -> SteerInput {
+        let decision = self.decide(simulation, index);
+        self.pending.push_back(decision);
+
+        let delay_ticks =
+            (self.difficulty.reaction_time().as_secs_f32() / delta.as_secs_f32()).round() as usize;
+        if self.pending.len() > delay_ticks {
+            self.pending.pop_front().unwrap_or_default()
+        } else {
+            SteerInput::default()
+        }
+    }
+
+    fn decide(&self, simulation: &Simulation, index: usize) -> SteerInput {
+        let player = &simulation.players[index];
+        if !player.alive {
+            return SteerInput::default();
+        }
+
+        let speed_factor = player.speed_factor(&simulation.rules);
+        let look_ahead = self.difficulty.look_ahead() * speed_factor.max(1.);
+
+        let mut scores = [0f32; RAY_ANGLES_DEG.len()];
+        for (score, angle) in scores.iter_mut().zip(RAY_ANGLES_DEG) {
+            // Any tap turns by a right angle, so other rays cannot be followed.
+            if simulation.rules.steering == Steering::RightAngle && angle.abs() % 90. != 0. {
+                *score = f32::NEG_INFINITY;
+                continue;
+            }
+            let dir = Quat::from_rotation_z(angle.to_radians()).mul_vec3(player.dir);
+            let free = free_distance(simulation, player, dir, look_ahead);
+            // Prefer going straight when several directions are equally free.
+            *score = free / look_ahead - angle.abs() / 900.;
+            *score += item_attraction(simulation, player, dir, free);
+        }
+
+        let best = scores
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index)
+            .unwrap_or(RAY_ANGLES_DEG.len() / 2);
+        let angle = RAY_ANGLES_DEG[best];
+
+        // The simulation swaps left and right while inverse steering is
+        // active, so ask for the opposite of where the bot wants to go.
+        let (left, right) = (angle > 0., angle < 0.);
+        if player.is_steering_inverse() {
+            SteerInput::digital(right, left)
+        } else {
+            SteerInput::digital(left, right)
+        }
+    }
+}
+
+/// Distance along `dir` until the first deadly pixel or the arena border,
+/// capped at `max_distance`. Wrapping arenas have no deadly border.
+fn free_distance(simulation: &Simulation, player: &Player, dir: Vec3, max_distance: f32) -> f32 {
+    let arena = &simulation.arena;
+    let harmless = simulation.harmless_colors();
+    let size = arena.size();
+    let step = 2. / size as f32;
+    // Start outside the head so the player's own fresh trail is not seen.
+    let start = player.radius(&simulation.rules) * 2. + step;
+    let mut distance = start;
+    while distance < max_distance {
+        let point = player.position + dir * distance;
+        match arena.texture_coord(point) {
+            Some((x, y)) => {
+                if !player.is_free_flying() && arena.is_deadly(x, y, &harmless) {
+                    return distance;
+                }
+            }
+            None => return distance,
+        }
+        distance += step;
+    }
+    max_distance
+}
+
+/// Bonus for rays leading towards useful items and malus for rays leading
+/// into harmful ones, if the item can be reached on the ray.
+fn item_attraction(simulation: &Simulation, player: &Player, dir: Vec3, free: f32) -> f32 {
+    let head = Vec2::new(player.position.x, player.position.y);
+    let dir = Vec2::new(dir.x, dir.y);
+    let mut attraction = 0.;
+    for field_item in &simulation.items {
+        let to_item = Vec2::new(field_item.position.x, field_item.position.y) - head;
+        let distance = to_item.length();
+        if distance > free || distance == 0. {
+            continue;
+        }
+        // Only items close to the ray count.
+        if to_item.normalize().dot(dir) < 0.95 {
+            continue;
+        }
+        attraction += item_value(&field_item.item) * (1. - distance / (free + f32::EPSILON));
+    }
+    attraction
+}
+
+fn item_value(item: &ItemDefinition) -> f32 {
+    match (item.effect, item.target) {
+        (EffectKind::Clear, _) => 0.4,
+        (EffectKind::MoreItems, _) => 0.1,
+        (
+            EffectKind::FreeFlying | EffectKind::Thin | EffectKind::Slowness,
+            ItemTarget::Collector,
+        ) => 0.5,
+        (_, ItemTarget::Collector) => -0.5,
+        (_, ItemTarget::Others) => 0.3,
+        // Affects the bot as much as everyone else.
+        (_, ItemTarget::Global) => 0.,
+    }
+}
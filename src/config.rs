@@ -0,0 +1,59 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Failure to read or write a RON config file.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Serialize(ron::Error),
+    Deserialize(ron::error::SpannedError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Serialize(e) => write!(f, "{}", e),
+            ConfigError::Deserialize(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl ConfigError {
+    /// The file does not exist, which usually just means nothing was saved
+    /// yet.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, ConfigError::Io(e) if e.kind() == std::io::ErrorKind::NotFound)
+    }
+}
+
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+    let text = fs::read_to_string(path)?;
+    ron::from_str(&text).map_err(ConfigError::Deserialize)
+}
+
+pub fn save<T: Serialize>(value: &T, path: &Path) -> Result<(), ConfigError> {
+    let text = ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())
+        .map_err(ConfigError::Serialize)?;
+    fs::write(path, text)?;
+    Ok(())
+}
+
+/// Like `save`, but without line breaks and indentation, for large files
+/// nobody edits by hand.
+pub fn save_compact<T: Serialize>(value: &T, path: &Path) -> Result<(), ConfigError> {
+    let text = ron::to_string(value).map_err(ConfigError::Serialize)?;
+    fs::write(path, text)?;
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+/// Entries kept in the table.
+const MAX_ENTRIES: usize = 10;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HighScore {
+    pub name: String,
+    pub score: u32,
+    /// Tenths of a second survived.
+    pub time_alive: u32,
+    pub items_collected: u32,
+}
+
+/// Best solo survival results, highest score first.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HighScores {
+    entries: Vec<HighScore>,
+}
+
+impl HighScores {
+    pub fn entries(&self) -> &[HighScore] {
+        &self.entries
+    }
+
+    /// Inserts `entry` behind all entries with the same or a higher score
+    /// and returns its rank, or `None` if it did not make it into the table.
+    pub fn add(&mut self, entry: HighScore) -> Option<usize> {
+        let rank = self
+            .entries
+            .iter()
+            .position(|other| other.score < entry.score)
+            .unwrap_or(self.entries.len());
+        if rank >= MAX_ENTRIES {
+            return None;
+        }
+        self.entries.insert(rank, entry);
+        self.entries.truncate(MAX_ENTRIES);
+        Some(rank)
+    }
+}
@@ -0,0 +1,311 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Who an item's effect applies to.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ItemTarget {
+    /// The player collecting the item.
+    Collector,
+    /// Everyone not in the collector's team.
+    Others,
+    /// Every player, or the whole field for field effects.
+    Global,
+}
+
+impl ItemTarget {
+    pub fn get_text(&self) -> String {
+        match self {
+            ItemTarget::Collector => "self",
+            ItemTarget::Others => "others",
+            ItemTarget::Global => "all",
+        }
+        .to_string()
+    }
+}
+
+/// What an item does. Player effects last as long as the item or
+/// `EffectRules` says, field effects happen once on pickup and ignore the
+/// target.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EffectKind {
+    /// Doubles the speed.
+    Speed,
+    /// Halves the speed.
+    Slowness,
+    /// Halves the trail width.
+    Thin,
+    /// Doubles the trail width.
+    Thick,
+    /// No trail and no collisions with trails.
+    FreeFlying,
+    /// Swaps left and right.
+    InverseSteer,
+    /// Removes all trails from the arena.
+    Clear,
+    /// Puts three more random items on the field.
+    MoreItems,
+}
+
+impl EffectKind {
+    pub fn is_field_effect(&self) -> bool {
+        matches!(self, EffectKind::Clear | EffectKind::MoreItems)
+    }
+}
+
+/// What happens when a player gets an effect they already have.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stacking {
+    /// The running effect starts over; there is never more than one.
+    Refresh,
+    /// Every pickup runs out on its own and adds the base strength once
+    /// more: twice as fast, three times as fast, and so on.
+    Additive,
+    /// Every pickup runs out on its own and doubles the strength.
+    Exponential,
+    /// Like `Exponential` up to the given number of pickups; further ones
+    /// restart the one closest to running out. `Cap(0)` counts as `Cap(1)`,
+    /// so the effect is never lost entirely.
+    Cap(usize),
+}
+
+impl Stacking {
+    /// Most effects of one kind a player can have at the same time.
+    pub fn limit(&self) -> usize {
+        match self {
+            Stacking::Refresh => 1,
+            Stacking::Additive | Stacking::Exponential => usize::MAX,
+            Stacking::Cap(limit) => (*limit).max(1),
+        }
+    }
+
+    /// Factor by which `count` effects of one kind scale speed or trail
+    /// width.
+    pub fn factor(&self, count: usize) -> f32 {
+        match self {
+            Stacking::Additive => 1. + count as f32,
+            Stacking::Refresh | Stacking::Exponential | Stacking::Cap(_) => 2f32.powi(count as i32),
+        }
+    }
+}
+
+/// Duration and stacking of one player effect.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct EffectRule {
+    pub duration_ms: u64,
+    pub stacking: Stacking,
+}
+
+impl EffectRule {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+}
+
+/// How every player effect behaves, whichever item it comes from.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct EffectRules {
+    pub speed: EffectRule,
+    pub slowness: EffectRule,
+    pub thin: EffectRule,
+    pub thick: EffectRule,
+    pub free_flying: EffectRule,
+    pub inverse_steer: EffectRule,
+}
+
+impl EffectRules {
+    /// Rule for `effect`, or `None` for field effects.
+    pub fn get(&self, effect: EffectKind) -> Option<&EffectRule> {
+        match effect {
+            EffectKind::Speed => Some(&self.speed),
+            EffectKind::Slowness => Some(&self.slowness),
+            EffectKind::Thin => Some(&self.thin),
+            EffectKind::Thick => Some(&self.thick),
+            EffectKind::FreeFlying => Some(&self.free_flying),
+            EffectKind::InverseSteer => Some(&self.inverse_steer),
+            EffectKind::Clear | EffectKind::MoreItems => None,
+        }
+    }
+}
+
+impl Default for EffectRules {
+    fn default() -> Self {
+        let stacking = EffectRule {
+            duration_ms: 5000,
+            stacking: Stacking::Exponential,
+        };
+        let refreshing = EffectRule {
+            duration_ms: 5000,
+            stacking: Stacking::Refresh,
+        };
+        EffectRules {
+            speed: stacking,
+            slowness: stacking,
+            thin: stacking,
+            thick: stacking,
+            free_flying: refreshing,
+            inverse_steer: refreshing,
+        }
+    }
+}
+
+/// One kind of item that can appear on the field.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ItemDefinition {
+    /// Text shown on the item's bubble.
+    pub label: String,
+    pub target: ItemTarget,
+    pub effect: EffectKind,
+    /// How long a player effect of this item lasts, or `None` for the
+    /// duration declared for the effect in `EffectRules`.
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    /// sRGB colour of the item's bubble.
+    pub color: [u8; 3],
+    /// Chance of being picked when a random item spawns, relative to the
+    /// other items of the set.
+    pub weight: u32,
+    /// Disabled items never spawn, but keep their weight for later.
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl ItemDefinition {
+    fn new(label: &str, target: ItemTarget, effect: EffectKind, weight: u32) -> Self {
+        let color = match target {
+            ItemTarget::Collector => [0, 128, 0],
+            ItemTarget::Others => [255, 0, 0],
+            ItemTarget::Global => [0, 0, 255],
+        };
+        ItemDefinition {
+            label: label.to_string(),
+            target,
+            effect,
+            duration_ms: None,
+            color,
+            weight,
+            enabled: true,
+        }
+    }
+
+    /// How long a player effect of this item lasts under `rules`.
+    pub fn duration(&self, rules: &EffectRules) -> Option<Duration> {
+        match self.duration_ms {
+            Some(duration_ms) => Some(Duration::from_millis(duration_ms)),
+            None => rules.get(self.effect).map(EffectRule::duration),
+        }
+    }
+
+    /// Weight used when picking a random item.
+    pub fn spawn_weight(&self) -> u32 {
+        match self.enabled {
+            true => self.weight,
+            false => 0,
+        }
+    }
+}
+
+/// The items of the original game. Each of the three targets is equally
+/// likely, and so is every item within a target.
+pub fn default_items() -> Vec<ItemDefinition> {
+    use EffectKind::*;
+    use ItemTarget::*;
+    vec![
+        ItemDefinition::new("fast", Collector, Speed, 2),
+        ItemDefinition::new("slow", Collector, Slowness, 2),
+        ItemDefinition::new("thin", Collector, Thin, 2),
+        ItemDefinition::new("thick", Collector, Thick, 2),
+        ItemDefinition::new("free", Collector, FreeFlying, 2),
+        ItemDefinition::new("<-->", Collector, InverseSteer, 2),
+        ItemDefinition::new("fast", Others, Speed, 3),
+        ItemDefinition::new("slow", Others, Slowness, 3),
+        ItemDefinition::new("thin", Others, Thin, 3),
+        ItemDefinition::new("thick", Others, Thick, 3),
+        ItemDefinition::new("clear", Global, Clear, 6),
+        ItemDefinition::new("more", Global, MoreItems, 6),
+    ]
+}
+
+/// Chance of every item of `items` to be picked by `pick`, from 0 to 1.
+pub fn chances(items: &[ItemDefinition]) -> Vec<f32> {
+    let total: u32 = items.iter().map(|item| item.spawn_weight()).sum();
+    items
+        .iter()
+        .map(|item| match total {
+            0 => 0.,
+            _ => item.spawn_weight() as f32 / total as f32,
+        })
+        .collect()
+}
+
+/// A random enabled item of `items`, picked by weight, or `None` if no
+/// enabled item has a positive weight.
+pub fn pick(items: &[ItemDefinition], rng: &mut impl Rng) -> Option<ItemDefinition> {
+    let distribution = WeightedIndex::new(items.iter().map(|item| item.spawn_weight())).ok()?;
+    Some(items[distribution.sample(rng)].clone())
+}
+
+/// A named item set with its weights and toggles, saved for later matches.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ItemPreset {
+    pub name: String,
+    pub items: Vec<ItemDefinition>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn additive_stacking_adds_and_exponential_doubles() {
+        assert_eq!(Stacking::Additive.factor(0), 1.);
+        assert_eq!(Stacking::Additive.factor(3), 4.);
+        assert_eq!(Stacking::Exponential.factor(0), 1.);
+        assert_eq!(Stacking::Exponential.factor(3), 8.);
+    }
+
+    #[test]
+    fn a_cap_of_zero_counts_as_a_cap_of_one() {
+        assert_eq!(Stacking::Cap(0).limit(), 1);
+        assert_eq!(Stacking::Cap(3).limit(), 3);
+    }
+
+    #[test]
+    fn disabled_and_weightless_items_are_never_picked() {
+        let mut items = default_items();
+        items.truncate(3);
+        items[0].enabled = false;
+        items[1].weight = 0;
+        assert_eq!(chances(&items), vec![0., 0., 1.]);
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            assert_eq!(pick(&items, &mut rng), Some(items[2].clone()));
+        }
+    }
+
+    #[test]
+    fn nothing_is_picked_without_weight() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut items = default_items();
+        for item in &mut items {
+            item.weight = 0;
+        }
+        assert_eq!(pick(&items, &mut rng), None);
+        assert!(chances(&items).iter().all(|chance| *chance == 0.));
+
+        let mut items = default_items();
+        for item in &mut items {
+            item.enabled = false;
+        }
+        assert_eq!(pick(&items, &mut rng), None);
+        assert_eq!(pick(&[], &mut rng), None);
+    }
+}
@@ -0,0 +1,9 @@
+pub mod bot;
+pub mod config;
+pub mod highscores;
+pub mod items;
+pub mod map;
+pub mod replay;
+pub mod rules;
+pub mod scoring;
+pub mod simulation;
@@ -3,28 +3,355 @@ use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
 use bevy::window::WindowResized;
 use bevy::{color::palettes::basic::*, prelude::*};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sneakysnakes::bot::{Bot, Difficulty};
+use sneakysnakes::config;
+use sneakysnakes::highscores::{HighScore, HighScores};
+use sneakysnakes::items::{self, ItemDefinition, ItemPreset};
+use sneakysnakes::map::{self, Map, MapPixel};
+use sneakysnakes::replay::Replay;
+use sneakysnakes::rules::{GameMode, GameRules, Steering, TERRITORY_TIME_LIMIT_MS};
+use sneakysnakes::scoring::Match;
+use sneakysnakes::simulation::{game_to_texture_vec, FieldItem, Simulation, SteerInput};
 use std::collections::HashSet;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+
+const REPLAY_DIR: &str = "replays";
+const CONTROLS_FILE: &str = "controls.ron";
+const RULES_FILE: &str = "rules.ron";
+const ITEMS_FILE: &str = "items.ron";
+const ITEM_PRESETS_FILE: &str = "item_presets.ron";
+const HIGH_SCORES_FILE: &str = "highscores.ron";
+const MAP_DIR: &str = "assets/maps";
+/// Time an expiring item stays visible or hidden while blinking.
+const ITEM_BLINK_INTERVAL_MS: u128 = 150;
+/// Width and height of maps created in the map editor.
+const NEW_MAP_SIZE: u32 = 256;
 
 #[derive(States, Debug, Clone, PartialEq, Eq, Hash)]
 enum AppState {
     MainMenu,
+    Controls,
+    Items,
     RoundStart,
     RoundActive,
     RoundOver,
+    MatchOver,
+    Replay,
+    MapEditor,
 }
 
 #[derive(Resource)]
 struct GameSettings {
-    number_of_players: u8,
+    /// Seed of the first round of a match, from which the later rounds derive
+    /// theirs; a fresh random seed is used per round if `None`.
+    seed: Option<u64>,
+    /// Simulation ticks per second, independent of the frame rate.
+    tick_rate: f64,
+    /// Score needed to win a match.
+    target_score: u32,
+    win_by_two: bool,
+    /// Whether players are grouped by `PlayerSlot::team`; everyone plays
+    /// for themselves otherwise.
+    teams: bool,
+    /// Image in `MAP_DIR` with the walls of the arena.
+    map: Option<PathBuf>,
 }
 
+const TICK_RATES: [f64; 4] = [30., 60., 120., 240.];
+
+/// Choices for `GameRules::sudden_death_after_ms` in the menu.
+const SUDDEN_DEATH_TIMES: [Option<u64>; 4] = [None, Some(30_000), Some(60_000), Some(120_000)];
+
+/// Choices for `GameRules::time_limit_ms` in the menu.
+const TIME_LIMITS: [Option<u64>; 4] = [None, Some(60_000), Some(120_000), Some(180_000)];
+
 impl Default for GameSettings {
     fn default() -> Self {
         GameSettings {
-            number_of_players: 2,
+            seed: None,
+            tick_rate: 60.,
+            target_score: 10,
+            win_by_two: true,
+            teams: false,
+            map: None,
+        }
+    }
+}
+
+/// One entry of the player roster. Enabled slots take part in the next round.
+struct PlayerSlot {
+    name: String,
+    color: Color,
+    steer_keys: (KeyCode, KeyCode),
+    enabled: bool,
+    /// Gamepad steering this slot instead of the keys.
+    gamepad: Option<Entity>,
+    gamepad_mode: GamepadMode,
+    /// Computer player steering this slot.
+    bot: Option<Difficulty>,
+    /// Index into `TEAMS`, used in team mode.
+    team: usize,
+}
+
+impl PlayerSlot {
+    fn new(
+        name: &str,
+        color: Color,
+        steer_keys: (KeyCode, KeyCode),
+        enabled: bool,
+        team: usize,
+    ) -> Self {
+        PlayerSlot {
+            name: name.to_string(),
+            color,
+            steer_keys,
+            enabled,
+            team,
+            gamepad: None,
+            gamepad_mode: GamepadMode::Digital,
+            bot: None,
+        }
+    }
+
+    fn input_source(&self) -> InputSource {
+        match (self.bot, self.gamepad) {
+            (Some(difficulty), _) => InputSource::Bot(difficulty),
+            (None, Some(gamepad)) => InputSource::Gamepad(gamepad, self.gamepad_mode),
+            (None, None) => InputSource::Keyboard(self.steer_keys.0, self.steer_keys.1),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+enum GamepadMode {
+    /// Shoulder buttons or d-pad turn at the full rate.
+    #[default]
+    Digital,
+    /// Left stick deflection scales the turn rate.
+    Analog,
+}
+
+/// What steers a player during a round.
+#[derive(Component, Clone, Copy)]
+enum InputSource {
+    Keyboard(KeyCode, KeyCode),
+    Gamepad(Entity, GamepadMode),
+    Bot(Difficulty),
+}
+
+impl InputSource {
+    fn steer_input(
+        &self,
+        keyboard_input: &ButtonInput<KeyCode>,
+        gamepads: &Query<&Gamepad>,
+    ) -> SteerInput {
+        match *self {
+            InputSource::Keyboard(left_key, right_key) => SteerInput::digital(
+                keyboard_input.pressed(left_key),
+                keyboard_input.pressed(right_key),
+            ),
+            InputSource::Gamepad(entity, mode) => {
+                let Ok(gamepad) = gamepads.get(entity) else {
+                    return SteerInput::default();
+                };
+                match mode {
+                    GamepadMode::Digital => SteerInput::digital(
+                        gamepad.pressed(GamepadButton::LeftTrigger)
+                            || gamepad.pressed(GamepadButton::DPadLeft),
+                        gamepad.pressed(GamepadButton::RightTrigger)
+                            || gamepad.pressed(GamepadButton::DPadRight),
+                    ),
+                    GamepadMode::Analog => {
+                        // Quantized, so recorded inputs stay short and exact.
+                        let x = (gamepad.left_stick().x * 16.).round() / 16.;
+                        SteerInput {
+                            left: (-x).max(0.),
+                            right: x.max(0.),
+                        }
+                    }
+                }
+            }
+            // Bots are steered by their `BotController`.
+            InputSource::Bot(_) => SteerInput::default(),
+        }
+    }
+}
+
+#[derive(Resource)]
+struct Roster {
+    slots: Vec<PlayerSlot>,
+}
+
+impl Default for Roster {
+    fn default() -> Self {
+        Roster {
+            slots: vec![
+                PlayerSlot::new(
+                    "RED",
+                    Color::from(RED),
+                    (KeyCode::ArrowLeft, KeyCode::ArrowRight),
+                    true,
+                    0,
+                ),
+                PlayerSlot::new(
+                    "GREEN",
+                    Color::from(GREEN),
+                    (KeyCode::KeyA, KeyCode::KeyD),
+                    true,
+                    1,
+                ),
+                PlayerSlot::new(
+                    "BLUE",
+                    Color::from(BLUE),
+                    (KeyCode::KeyV, KeyCode::KeyN),
+                    false,
+                    1,
+                ),
+                PlayerSlot::new(
+                    "YELLOW",
+                    Color::from(YELLOW),
+                    (KeyCode::KeyJ, KeyCode::KeyL),
+                    false,
+                    0,
+                ),
+                PlayerSlot::new(
+                    "MAGENTA",
+                    Color::from(FUCHSIA),
+                    (KeyCode::Numpad4, KeyCode::Numpad6),
+                    false,
+                    0,
+                ),
+                PlayerSlot::new(
+                    "CYAN",
+                    Color::from(AQUA),
+                    (KeyCode::Digit1, KeyCode::KeyQ),
+                    false,
+                    1,
+                ),
+            ],
+        }
+    }
+}
+
+impl Roster {
+    fn enabled_count(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.enabled).count()
+    }
+
+    /// Team of every enabled slot, in the order the players join a round.
+    /// Without team mode every player is a team of its own, numbered like
+    /// the player.
+    fn player_teams(&self, team_mode: bool) -> Vec<usize> {
+        self.slots
+            .iter()
+            .filter(|slot| slot.enabled)
+            .enumerate()
+            .map(|(index, slot)| if team_mode { slot.team } else { index })
+            .collect()
+    }
+
+    /// Pairs of slot indices that share a steer key, including slots using
+    /// the same key for left and right.
+    fn key_clashes(&self) -> Vec<(usize, usize, KeyCode)> {
+        let mut clashes = Vec::new();
+        for (a, slot_a) in self.slots.iter().enumerate() {
+            if slot_a.steer_keys.0 == slot_a.steer_keys.1 {
+                clashes.push((a, a, slot_a.steer_keys.0));
+            }
+            for (b, slot_b) in self.slots.iter().enumerate().skip(a + 1) {
+                for key in [slot_a.steer_keys.0, slot_a.steer_keys.1] {
+                    if key == slot_b.steer_keys.0 || key == slot_b.steer_keys.1 {
+                        clashes.push((a, b, key));
+                    }
+                }
+            }
+        }
+        clashes
+    }
+}
+
+/// Steer keys of one roster slot as stored in the controls file.
+#[derive(Serialize, Deserialize)]
+struct SlotKeys {
+    name: String,
+    left: KeyCode,
+    right: KeyCode,
+    #[serde(default)]
+    gamepad_mode: GamepadMode,
+    #[serde(default)]
+    bot: Option<Difficulty>,
+    #[serde(default)]
+    team: Option<usize>,
+}
+
+fn load_key_bindings(mut roster: ResMut<Roster>) {
+    let bindings: Vec<SlotKeys> = match config::load(Path::new(CONTROLS_FILE)) {
+        Ok(bindings) => bindings,
+        Err(e) if e.is_not_found() => return,
+        Err(e) => {
+            warn!("could not load {}: {}", CONTROLS_FILE, e);
+            return;
+        }
+    };
+    for binding in bindings {
+        if let Some(slot) = roster
+            .slots
+            .iter_mut()
+            .find(|slot| slot.name == binding.name)
+        {
+            slot.steer_keys = (binding.left, binding.right);
+            slot.gamepad_mode = binding.gamepad_mode;
+            slot.bot = binding.bot;
+            if let Some(team) = binding.team.filter(|team| *team < TEAMS.len()) {
+                slot.team = team;
+            }
+        }
+    }
+}
+
+fn save_key_bindings(roster: Res<Roster>) {
+    let bindings: Vec<SlotKeys> = roster
+        .slots
+        .iter()
+        .map(|slot| SlotKeys {
+            name: slot.name.clone(),
+            left: slot.steer_keys.0,
+            right: slot.steer_keys.1,
+            gamepad_mode: slot.gamepad_mode,
+            bot: slot.bot,
+            team: Some(slot.team),
+        })
+        .collect();
+    if let Err(e) = config::save(&bindings, Path::new(CONTROLS_FILE)) {
+        warn!("could not save {}: {}", CONTROLS_FILE, e);
+    }
+}
+
+/// Rules for new rounds, read from `RULES_FILE` at startup. A list of item
+/// definitions in `ITEMS_FILE` replaces the item set of the rules.
+#[derive(Resource, Deref, DerefMut, Default)]
+struct Rules(GameRules);
+
+fn load_rules(mut commands: Commands) {
+    let mut rules = match config::load(Path::new(RULES_FILE)) {
+        Ok(rules) => rules,
+        Err(e) => {
+            if !e.is_not_found() {
+                warn!("could not load {}: {}", RULES_FILE, e);
+            }
+            GameRules::default()
+        }
+    };
+    match config::load::<Vec<ItemDefinition>>(Path::new(ITEMS_FILE)) {
+        Ok(items) => rules.items = items,
+        Err(e) => {
+            if !e.is_not_found() {
+                warn!("could not load {}: {}", ITEMS_FILE, e);
+            }
         }
     }
+    commands.insert_resource(Rules(rules));
 }
 
 #[derive(Resource)]
@@ -66,8 +393,30 @@ fn main() {
         }))
         .insert_state::<AppState>(AppState::MainMenu)
         .insert_resource(GameSettings::default())
+        .insert_resource(Roster::default())
         .insert_resource(window_size)
+        .add_systems(
+            Startup,
+            (
+                load_key_bindings,
+                load_rules,
+                load_high_scores,
+                load_item_presets,
+            ),
+        )
         .add_systems(Update, on_resize_system)
+        .add_systems(OnEnter(AppState::Controls), setup_controls)
+        .add_systems(
+            OnExit(AppState::Controls),
+            (cleanup_controls, save_key_bindings),
+        )
+        .add_systems(Update, update_controls.run_if(in_state(AppState::Controls)))
+        .add_systems(OnEnter(AppState::Items), setup_items_screen)
+        .add_systems(OnExit(AppState::Items), cleanup_items_screen)
+        .add_systems(
+            Update,
+            update_items_screen.run_if(in_state(AppState::Items)),
+        )
         .add_systems(OnEnter(AppState::MainMenu), cleanup_in_game)
         .add_systems(
             OnEnter(AppState::MainMenu),
@@ -77,9 +426,30 @@ fn main() {
         .add_systems(OnEnter(AppState::RoundStart), setup_in_game)
         .add_systems(
             OnEnter(AppState::RoundStart),
-            move_players_a_bit.after(setup_in_game),
+            (
+                move_players_a_bit,
+                setup_seed_text,
+                setup_countdown,
+                setup_practice_text,
+            )
+                .after(setup_in_game),
+        )
+        .add_systems(OnExit(AppState::RoundStart), cleanup_seed_text)
+        .add_systems(
+            OnEnter(AppState::RoundOver),
+            (
+                score_round,
+                record_high_score,
+                setup_round_over.after(score_round).after(record_high_score),
+                save_replay,
+            ),
+        )
+        .add_systems(OnEnter(AppState::MatchOver), setup_match_over)
+        .add_systems(OnExit(AppState::MatchOver), cleanup_match_over)
+        .add_systems(
+            Update,
+            update_match_over.run_if(in_state(AppState::MatchOver)),
         )
-        .add_systems(OnEnter(AppState::RoundOver), setup_round_over)
         .add_systems(OnExit(AppState::RoundOver), cleanup_in_game)
         .add_systems(OnExit(AppState::RoundOver), cleanup_round_over)
         .add_systems(
@@ -88,31 +458,61 @@ fn main() {
         )
         .add_systems(
             Update,
-            update_main_menu.run_if(in_state(AppState::MainMenu)),
+            (assign_gamepads, update_main_menu.after(assign_gamepads))
+                .run_if(in_state(AppState::MainMenu)),
+        )
+        .add_systems(
+            Update,
+            update_round_active.run_if(in_state(AppState::RoundActive)),
+        )
+        .add_systems(
+            FixedUpdate,
+            game_logic.run_if(in_state(AppState::RoundActive)),
+        )
+        .add_systems(
+            Update,
+            check_round_over.run_if(in_state(AppState::RoundActive)),
+        )
+        .add_systems(
+            Update,
+            (draw_simulation, draw_items).run_if(
+                in_state(AppState::RoundStart)
+                    .or(in_state(AppState::RoundActive))
+                    .or(in_state(AppState::Replay)),
+            ),
+        )
+        .add_systems(OnEnter(AppState::Replay), (setup_replay, setup_countdown))
+        .add_systems(
+            OnExit(AppState::Replay),
+            (cleanup_replay, cleanup_countdown),
         )
-        .add_systems(Update, spawn_items.run_if(in_state(AppState::RoundActive)))
+        .add_systems(OnEnter(AppState::MainMenu), cleanup_countdown)
+        .add_systems(OnEnter(AppState::MainMenu), cleanup_practice_text)
         .add_systems(
             Update,
-            update_player_item_effects.run_if(in_state(AppState::RoundActive)),
+            update_practice_text
+                .run_if(in_state(AppState::RoundStart).or(in_state(AppState::RoundActive))),
         )
         .add_systems(
             Update,
-            game_logic
-                .run_if(in_state(AppState::RoundActive))
-                .after(update_player_item_effects),
+            update_practice.run_if(in_state(AppState::RoundActive)),
         )
         .add_systems(
             Update,
-            check_round_over
-                .run_if(in_state(AppState::RoundActive))
-                .after(game_logic),
+            update_countdown.run_if(
+                in_state(AppState::RoundStart)
+                    .or(in_state(AppState::RoundActive))
+                    .or(in_state(AppState::Replay)),
+            ),
         )
+        .add_systems(Update, update_replay.run_if(in_state(AppState::Replay)))
+        .add_systems(OnEnter(AppState::MapEditor), setup_map_editor)
+        .add_systems(OnExit(AppState::MapEditor), cleanup_map_editor)
         .add_systems(
             Update,
-            item_collection
-                .run_if(in_state(AppState::RoundActive))
-                .after(game_logic),
+            update_map_editor.run_if(in_state(AppState::MapEditor)),
         )
+        .add_systems(FixedUpdate, replay_logic.run_if(in_state(AppState::Replay)))
         .add_systems(
             Update,
             update_round_over.run_if(in_state(AppState::RoundOver)),
@@ -145,115 +545,1079 @@ fn update_main_menu(
     mut commands: Commands,
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mut settings: ResMut<GameSettings>,
+    mut rules: ResMut<Rules>,
+    mut roster: ResMut<Roster>,
     mut query: Query<&mut Text2d>,
 ) {
     if keyboard_input.just_pressed(KeyCode::Space) {
+        commands.insert_resource(CurrentMatch(Match::new(
+            roster.player_teams(settings.teams),
+            settings.target_score,
+            settings.win_by_two,
+        )));
         commands.set_state(AppState::RoundStart);
     }
-    if keyboard_input.just_pressed(KeyCode::ArrowLeft) {
-        if settings.number_of_players > 1 {
-            settings.number_of_players -= 1;
+    if keyboard_input.just_pressed(KeyCode::KeyC) {
+        commands.set_state(AppState::Controls);
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyI) {
+        commands.set_state(AppState::Items);
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyP) {
+        commands.set_state(AppState::MapEditor);
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyR) {
+        match Replay::load_latest(Path::new(REPLAY_DIR)) {
+            Ok(replay) => start_replay(&mut commands, replay),
+            Err(e) => warn!("could not load replay: {}", e),
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowLeft) && roster.enabled_count() > 1 {
+        if let Some(slot) = roster.slots.iter_mut().rev().find(|slot| slot.enabled) {
+            slot.enabled = false;
         }
     }
     if keyboard_input.just_pressed(KeyCode::ArrowRight) {
-        if settings.number_of_players < 6 {
-            settings.number_of_players += 1;
+        if let Some(slot) = roster.slots.iter_mut().find(|slot| !slot.enabled) {
+            slot.enabled = true;
+        }
+    }
+    for key in keyboard_input.get_just_pressed() {
+        if let Some(digit) = digit_of_key(*key) {
+            let seed = settings.seed.unwrap_or(0);
+            if let Some(seed) = seed.checked_mul(10).and_then(|s| s.checked_add(digit)) {
+                settings.seed = Some(seed);
+            }
+        }
+    }
+    let tick_rate_index = TICK_RATES
+        .iter()
+        .position(|rate| *rate == settings.tick_rate)
+        .unwrap_or(1);
+    if keyboard_input.just_pressed(KeyCode::ArrowDown) && tick_rate_index > 0 {
+        settings.tick_rate = TICK_RATES[tick_rate_index - 1];
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowUp) && tick_rate_index < TICK_RATES.len() - 1 {
+        settings.tick_rate = TICK_RATES[tick_rate_index + 1];
+    }
+    if keyboard_input.just_pressed(KeyCode::Minus) && settings.target_score > 1 {
+        settings.target_score -= 1;
+    }
+    if keyboard_input.just_pressed(KeyCode::Equal) {
+        settings.target_score += 1;
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyW) {
+        settings.win_by_two = !settings.win_by_two;
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyE) {
+        rules.wrap_around = !rules.wrap_around;
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyT) {
+        settings.teams = !settings.teams;
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyM) {
+        match map::list_maps(Path::new(MAP_DIR)) {
+            Ok(maps) => {
+                let next = match &settings.map {
+                    Some(current) => maps.iter().skip_while(|map| *map != current).nth(1),
+                    None => maps.first(),
+                };
+                settings.map = next.cloned();
+            }
+            Err(e) => warn!("could not list maps: {}", e),
         }
     }
+    if keyboard_input.just_pressed(KeyCode::KeyS) {
+        let index = SUDDEN_DEATH_TIMES
+            .iter()
+            .position(|time| *time == rules.sudden_death_after_ms)
+            .map_or(0, |index| (index + 1) % SUDDEN_DEATH_TIMES.len());
+        rules.sudden_death_after_ms = SUDDEN_DEATH_TIMES[index];
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyY) {
+        rules.mode = match rules.mode {
+            GameMode::Survival => GameMode::Territory,
+            GameMode::Territory => GameMode::Practice,
+            GameMode::Practice => GameMode::Survival,
+        };
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyG) {
+        rules.steering = match rules.steering {
+            Steering::Smooth => Steering::RightAngle,
+            Steering::RightAngle => Steering::Smooth,
+        };
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyL) {
+        let index = TIME_LIMITS
+            .iter()
+            .position(|time| *time == rules.time_limit_ms)
+            .map_or(0, |index| (index + 1) % TIME_LIMITS.len());
+        rules.time_limit_ms = TIME_LIMITS[index];
+    }
+    if keyboard_input.just_pressed(KeyCode::Backspace) {
+        settings.seed = settings
+            .seed
+            .map(|seed| seed / 10)
+            .filter(|seed| *seed != 0);
+    }
     if let Ok(mut text) = query.get_single_mut() {
-        text.0 = format!("Number of players: {}", settings.number_of_players);
+        let seed = match settings.seed {
+            Some(seed) => seed.to_string(),
+            None => "random".to_string(),
+        };
+        let win_by_two = match settings.win_by_two {
+            true => " (win by two)",
+            false => "",
+        };
+        let edges = match rules.wrap_around {
+            true => "wrap around",
+            false => "deadly",
+        };
+        let teams = match settings.teams {
+            true => TEAMS
+                .iter()
+                .enumerate()
+                .filter_map(|(team, (name, _))| {
+                    let members = roster
+                        .slots
+                        .iter()
+                        .filter(|slot| slot.enabled && slot.team == team)
+                        .count();
+                    (members > 0).then(|| format!("{} {}", name, members))
+                })
+                .collect::<Vec<_>>()
+                .join(" vs "),
+            false => "off".to_string(),
+        };
+        let solo = match (roster.enabled_count(), rules.mode) {
+            (1, GameMode::Survival) => " (solo survival)",
+            _ => "",
+        };
+        let time_limit = match (rules.time_limit_ms, rules.mode) {
+            (_, GameMode::Practice) => "off".to_string(),
+            (Some(limit), _) => format!("{} s", limit / 1000),
+            (None, GameMode::Territory) => format!("{} s", TERRITORY_TIME_LIMIT_MS / 1000),
+            (None, GameMode::Survival) => "off".to_string(),
+        };
+        let map = match &settings.map {
+            Some(path) => map::map_name(path),
+            None => "none".to_string(),
+        };
+        let sudden_death = match rules.sudden_death_after_ms {
+            Some(after) => format!("after {} s", after / 1000),
+            None => "off".to_string(),
+        };
+        let items_on = rules.items.iter().filter(|item| item.enabled).count();
+        text.0 = format!(
+            "Number of players: {}{}\nSeed: {}\nTick rate: {} Hz\nPlay to {}{}\nMode: {}\nSteering: {}\nEdges: {}\nTeams: {}\nSudden death: {}\nTime limit: {}\nMap: {}\nItems: {} of {} on\n\nC: controls  I: items  R: last replay  P: map editor",
+            roster.enabled_count(),
+            solo,
+            seed,
+            settings.tick_rate,
+            settings.target_score,
+            win_by_two,
+            rules.mode.get_text(),
+            rules.steering.get_text(),
+            edges,
+            teams,
+            sudden_death,
+            time_limit,
+            map,
+            items_on,
+            rules.items.len()
+        );
     }
 }
 
-fn setup_round_over(mut commands: Commands, query: Query<&Player>) {
-    let mut winner_name = None;
-    for player in &query {
-        if player.alive {
-            winner_name = Some(player.name.clone());
-        }
-    }
-    let text = match winner_name {
-        Some(name) => format!("Player {} won!", name),
-        None => "lol! Nobody won this round".to_string(),
-    };
+/// Side of a slot whose key is being captured on the controls screen.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SteerSide {
+    Left,
+    Right,
+}
+
+#[derive(Resource)]
+struct ControlsScreen {
+    selected: usize,
+    capturing: Option<SteerSide>,
+}
+
+#[derive(Component)]
+struct ControlsText;
+
+fn setup_controls(mut commands: Commands) {
+    commands.insert_resource(ControlsScreen {
+        selected: 0,
+        capturing: None,
+    });
     commands.spawn((
-        Text2d::new(text),
+        ControlsText,
+        Text2d::new(""),
         Transform::from_translation(Vec3::new(0., 0., 2.)).with_scale(Vec3::new(
             1. / 512.,
             1. / 512.,
             1.,
         )),
         TextFont {
-            font_size: 40.0,
+            font_size: 24.0,
             ..default()
         },
     ));
 }
 
-fn on_resize_system(
-    mut resize_reader: EventReader<WindowResized>,
-    mut window_size: ResMut<WindowSize>,
+fn cleanup_controls(mut commands: Commands, query: Query<Entity, With<ControlsText>>) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<ControlsScreen>();
+}
+
+/// Puts newly connected gamepads into free human slots and releases the
+/// slots of disconnected ones.
+fn assign_gamepads(mut roster: ResMut<Roster>, gamepads: Query<Entity, With<Gamepad>>) {
+    for slot in &mut roster.slots {
+        if slot
+            .gamepad
+            .is_some_and(|gamepad| !gamepads.contains(gamepad))
+        {
+            slot.gamepad = None;
+            slot.enabled = false;
+        }
+    }
+    for gamepad in &gamepads {
+        if roster
+            .slots
+            .iter()
+            .any(|slot| slot.gamepad == Some(gamepad))
+        {
+            continue;
+        }
+        // Bot slots would ignore the pad, so its player could not steer.
+        if let Some(slot) = roster
+            .slots
+            .iter_mut()
+            .find(|slot| !slot.enabled && slot.bot.is_none())
+        {
+            slot.gamepad = Some(gamepad);
+            slot.enabled = true;
+        }
+    }
+}
+
+/// Up/Down selects a slot, Enter captures its left and then its right key,
+/// M switches its gamepad between digital and analog steering and B cycles
+/// between a human and the bot difficulties.
+fn update_controls(
     mut commands: Commands,
-    query: Option<Single<Entity, With<Camera>>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut screen: ResMut<ControlsScreen>,
+    mut roster: ResMut<Roster>,
+    mut query: Query<&mut Text2d, With<ControlsText>>,
 ) {
-    if let Some(e) = resize_reader.read().next() {
-        window_size.width = e.width;
-        window_size.height = e.height;
-
-        if let Some(entity) = query.map(|single| single.into_inner()) {
-            commands.entity(entity).despawn();
+    let selected = screen.selected;
+    match screen.capturing {
+        Some(side) => {
+            if keyboard_input.just_pressed(KeyCode::Escape) {
+                screen.capturing = None;
+            } else if let Some(key) = keyboard_input.get_just_pressed().next() {
+                let steer_keys = &mut roster.slots[selected].steer_keys;
+                match side {
+                    SteerSide::Left => {
+                        steer_keys.0 = *key;
+                        screen.capturing = Some(SteerSide::Right);
+                    }
+                    SteerSide::Right => {
+                        steer_keys.1 = *key;
+                        screen.capturing = None;
+                    }
+                }
+            }
+        }
+        None => {
+            if keyboard_input.just_pressed(KeyCode::Escape) {
+                commands.set_state(AppState::MainMenu);
+            }
+            if keyboard_input.just_pressed(KeyCode::ArrowUp) && selected > 0 {
+                screen.selected -= 1;
+            }
+            if keyboard_input.just_pressed(KeyCode::ArrowDown) && selected < roster.slots.len() - 1
+            {
+                screen.selected += 1;
+            }
+            if keyboard_input.just_pressed(KeyCode::Enter) {
+                screen.capturing = Some(SteerSide::Left);
+            }
+            if keyboard_input.just_pressed(KeyCode::KeyB) {
+                let slot = &mut roster.slots[selected];
+                slot.bot = match slot.bot {
+                    None => Some(Difficulty::Easy),
+                    Some(Difficulty::Easy) => Some(Difficulty::Medium),
+                    Some(Difficulty::Medium) => Some(Difficulty::Hard),
+                    Some(Difficulty::Hard) => None,
+                };
+            }
+            if keyboard_input.just_pressed(KeyCode::KeyG) {
+                let slot = &mut roster.slots[selected];
+                slot.team = (slot.team + 1) % TEAMS.len();
+            }
+            if keyboard_input.just_pressed(KeyCode::KeyM) {
+                let slot = &mut roster.slots[selected];
+                slot.gamepad_mode = match slot.gamepad_mode {
+                    GamepadMode::Digital => GamepadMode::Analog,
+                    GamepadMode::Analog => GamepadMode::Digital,
+                };
+            }
         }
+    }
 
-        let smallest_dim = window_size.get_smallest_dimension();
-        commands.spawn((
-            Camera2d,
-            Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)).with_scale(Vec3::new(
-                2. / smallest_dim,
-                2. / smallest_dim,
-                1.,
-            )),
-        ));
+    if let Ok(mut text) = query.get_single_mut() {
+        let mut lines = vec!["Controls".to_string(), String::new()];
+        for (index, slot) in roster.slots.iter().enumerate() {
+            let (left, right) = match (index == screen.selected, screen.capturing) {
+                (true, Some(SteerSide::Left)) => {
+                    ("?".to_string(), format!("{:?}", slot.steer_keys.1))
+                }
+                (true, Some(SteerSide::Right)) => {
+                    (format!("{:?}", slot.steer_keys.0), "?".to_string())
+                }
+                _ => (
+                    format!("{:?}", slot.steer_keys.0),
+                    format!("{:?}", slot.steer_keys.1),
+                ),
+            };
+            let marker = if index == screen.selected { ">" } else { " " };
+            let gamepad = match (slot.bot, slot.gamepad, slot.gamepad_mode) {
+                (Some(difficulty), _, _) => format!(" [bot, {}]", difficulty.get_text()),
+                (None, Some(_), GamepadMode::Digital) => " [pad, digital]".to_string(),
+                (None, Some(_), GamepadMode::Analog) => " [pad, analog]".to_string(),
+                (None, None, _) => String::new(),
+            };
+            lines.push(format!(
+                "{} {}: {} / {}{} ({} team)",
+                marker, slot.name, left, right, gamepad, TEAMS[slot.team].0
+            ));
+        }
+        lines.push(String::new());
+        for (a, b, key) in roster.key_clashes() {
+            if a == b {
+                lines.push(format!(
+                    "Warning: {} uses {:?} twice",
+                    roster.slots[a].name, key
+                ));
+            } else {
+                lines.push(format!(
+                    "Warning: {} and {} share {:?}",
+                    roster.slots[a].name, roster.slots[b].name, key
+                ));
+            }
+        }
+        lines.push("Enter: rebind  M: pad mode  B: bot  G: team  Esc: back".to_string());
+        text.0 = lines.join("\n");
     }
 }
 
-fn update_round_start(mut commands: Commands, keyboard_input: Res<ButtonInput<KeyCode>>) {
-    if keyboard_input.just_pressed(KeyCode::Space) {
-        commands.set_state(AppState::RoundActive);
-    }
-    if keyboard_input.just_pressed(KeyCode::Escape) {
-        commands.set_state(AppState::MainMenu);
+/// Item sets saved on the items screen, in `ITEM_PRESETS_FILE`.
+#[derive(Resource, Default, Deref, DerefMut)]
+struct ItemPresets(Vec<ItemPreset>);
+
+fn load_item_presets(mut commands: Commands) {
+    let presets = match config::load(Path::new(ITEM_PRESETS_FILE)) {
+        Ok(presets) => presets,
+        Err(e) => {
+            if !e.is_not_found() {
+                warn!("could not load {}: {}", ITEM_PRESETS_FILE, e);
+            }
+            Vec::new()
+        }
+    };
+    commands.insert_resource(ItemPresets(presets));
+}
+
+fn save_item_presets(presets: &ItemPresets) {
+    if let Err(e) = config::save(&presets.0, Path::new(ITEM_PRESETS_FILE)) {
+        warn!("could not save {}: {}", ITEM_PRESETS_FILE, e);
     }
 }
 
-fn update_round_over(mut commands: Commands, keyboard_input: Res<ButtonInput<KeyCode>>) {
-    if keyboard_input.just_pressed(KeyCode::Space) {
-        commands.set_state(AppState::RoundStart);
+#[derive(Resource)]
+struct ItemsScreen {
+    selected: usize,
+    /// Preset the current item set was loaded from, if it was not changed
+    /// since.
+    preset: Option<usize>,
+}
+
+#[derive(Component)]
+struct ItemsText;
+
+fn setup_items_screen(mut commands: Commands) {
+    commands.insert_resource(ItemsScreen {
+        selected: 0,
+        preset: None,
+    });
+    commands.spawn((
+        ItemsText,
+        Text2d::new(""),
+        Transform::from_translation(Vec3::new(0., 0., 2.)).with_scale(Vec3::new(
+            1. / 512.,
+            1. / 512.,
+            1.,
+        )),
+        TextFont {
+            font_size: 22.0,
+            ..default()
+        },
+    ));
+}
+
+fn cleanup_items_screen(mut commands: Commands, query: Query<Entity, With<ItemsText>>) {
+    for entity in &query {
+        commands.entity(entity).despawn();
     }
+    commands.remove_resource::<ItemsScreen>();
+}
+
+/// Up/Down selects an item, Left/Right changes its weight and Enter turns it
+/// on or off. Tab loads the next preset, S saves the items as a new preset,
+/// D deletes the loaded preset and R restores the default items.
+fn update_items_screen(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut screen: ResMut<ItemsScreen>,
+    mut rules: ResMut<Rules>,
+    mut presets: ResMut<ItemPresets>,
+    mut query: Query<&mut Text2d, With<ItemsText>>,
+) {
     if keyboard_input.just_pressed(KeyCode::Escape) {
         commands.set_state(AppState::MainMenu);
     }
+    let selected = screen.selected;
+    if keyboard_input.just_pressed(KeyCode::ArrowUp) && selected > 0 {
+        screen.selected -= 1;
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowDown) && selected + 1 < rules.items.len() {
+        screen.selected += 1;
+    }
+    if let Some(item) = rules.items.get_mut(selected) {
+        if keyboard_input.just_pressed(KeyCode::ArrowLeft) && item.weight > 0 {
+            item.weight -= 1;
+            screen.preset = None;
+        }
+        if keyboard_input.just_pressed(KeyCode::ArrowRight) {
+            item.weight += 1;
+            screen.preset = None;
+        }
+        if keyboard_input.just_pressed(KeyCode::Enter) {
+            item.enabled = !item.enabled;
+            screen.preset = None;
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Tab) && !presets.is_empty() {
+        let index = screen.preset.map_or(0, |index| (index + 1) % presets.len());
+        rules.items = presets[index].items.clone();
+        screen.preset = Some(index);
+        screen.selected = screen.selected.min(rules.items.len().saturating_sub(1));
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyS) {
+        let name = format!("Preset {}", presets.len() + 1);
+        presets.push(ItemPreset {
+            name,
+            items: rules.items.clone(),
+        });
+        screen.preset = Some(presets.len() - 1);
+        save_item_presets(&presets);
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyD) {
+        if let Some(index) = screen.preset.take() {
+            presets.remove(index);
+            save_item_presets(&presets);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyR) {
+        rules.items = items::default_items();
+        screen.preset = None;
+        screen.selected = screen.selected.min(rules.items.len().saturating_sub(1));
+    }
+
+    if let Ok(mut text) = query.get_single_mut() {
+        let preset = match screen.preset {
+            Some(index) => presets[index].name.clone(),
+            None => "custom".to_string(),
+        };
+        let mut lines = vec![format!("Items ({})", preset), String::new()];
+        let chances = items::chances(&rules.items);
+        for (index, (item, chance)) in rules.items.iter().zip(chances).enumerate() {
+            let marker = if index == screen.selected { ">" } else { " " };
+            let state = match item.enabled {
+                true => format!("{:.0}%", chance * 100.),
+                false => "off".to_string(),
+            };
+            lines.push(format!(
+                "{} {} ({}): weight {}, {}",
+                marker,
+                item.label,
+                item.target.get_text(),
+                item.weight,
+                state
+            ));
+        }
+        lines.push(String::new());
+        lines.push(
+            "Left/Right: weight  Enter: on/off  Tab: next preset\nS: save preset  D: delete preset  R: defaults  Esc: back"
+                .to_string(),
+        );
+        text.0 = lines.join("\n");
+    }
 }
 
-fn cleanup_round_over(mut commands: Commands, query: Query<Entity, With<Text2d>>) {
-    for entity in &query {
-        commands.entity(entity).despawn();
+fn digit_of_key(key: KeyCode) -> Option<u64> {
+    match key {
+        KeyCode::Digit0 | KeyCode::Numpad0 => Some(0),
+        KeyCode::Digit1 | KeyCode::Numpad1 => Some(1),
+        KeyCode::Digit2 | KeyCode::Numpad2 => Some(2),
+        KeyCode::Digit3 | KeyCode::Numpad3 => Some(3),
+        KeyCode::Digit4 | KeyCode::Numpad4 => Some(4),
+        KeyCode::Digit5 | KeyCode::Numpad5 => Some(5),
+        KeyCode::Digit6 | KeyCode::Numpad6 => Some(6),
+        KeyCode::Digit7 | KeyCode::Numpad7 => Some(7),
+        KeyCode::Digit8 | KeyCode::Numpad8 => Some(8),
+        KeyCode::Digit9 | KeyCode::Numpad9 => Some(9),
+        _ => None,
     }
 }
 
-fn setup_in_game(
+fn setup_round_over(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
     settings: Res<GameSettings>,
-    mut images: ResMut<Assets<Image>>,
-    window_size: Res<WindowSize>,
+    simulation: Res<RoundSimulation>,
+    current_match: Res<CurrentMatch>,
+    high_scores: Res<HighScoreTable>,
 ) {
+    let text = if is_solo_survival(&simulation) {
+        solo_summary(&simulation, &high_scores)
+    } else {
+        let text = match simulation.round_winner() {
+            Some(team) => format!("{} won!", team_name(team, &settings, &simulation)),
+            None => "lol! Nobody won this round".to_string(),
+        };
+        let text = match simulation.is_time_up() {
+            true => format!("Time's up! {}", text),
+            false => text,
+        };
+        let text = match simulation.rules.mode {
+            GameMode::Territory => format!("{}\n\n{}", text, territory_breakdown(&simulation)),
+            GameMode::Survival | GameMode::Practice => text,
+        };
+        format!(
+            "{}\n\n{}",
+            text,
+            scoreboard(&current_match, &settings, &simulation)
+        )
+    };
+    commands.spawn((
+        Text2d::new(text),
+        Transform::from_translation(Vec3::new(0., 0., 2.)).with_scale(Vec3::new(
+            1. / 512.,
+            1. / 512.,
+            1.,
+        )),
+        TextFont {
+            font_size: 40.0,
+            ..default()
+        },
+    ));
+}
+
+/// Share of the arena every player painted, largest first.
+fn territory_breakdown(simulation: &Simulation) -> String {
+    let mut shares: Vec<(usize, f32)> = simulation
+        .territory_shares()
+        .into_iter()
+        .enumerate()
+        .collect();
+    shares.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    shares
+        .iter()
+        .map(|(index, share)| format!("{}: {:.1}%", simulation.players[*index].name, share * 100.))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn score_round(mut current_match: ResMut<CurrentMatch>, simulation: Res<RoundSimulation>) {
+    current_match.add_round(&simulation.round_points());
+}
+
+/// Solo survival results, saved to `HIGH_SCORES_FILE`.
+#[derive(Resource, Default)]
+struct HighScoreTable {
+    scores: HighScores,
+    /// Rank of the entry added at the end of the last solo round.
+    latest: Option<usize>,
+}
+
+fn load_high_scores(mut commands: Commands) {
+    let scores = match config::load(Path::new(HIGH_SCORES_FILE)) {
+        Ok(scores) => scores,
+        Err(e) => {
+            if !e.is_not_found() {
+                warn!("could not load {}: {}", HIGH_SCORES_FILE, e);
+            }
+            HighScores::default()
+        }
+    };
+    commands.insert_resource(HighScoreTable {
+        scores,
+        latest: None,
+    });
+}
+
+fn record_high_score(mut high_scores: ResMut<HighScoreTable>, simulation: Res<RoundSimulation>) {
+    high_scores.latest = None;
+    if !is_solo_survival(&simulation) {
+        return;
+    }
+    let player = &simulation.players[0];
+    high_scores.latest = high_scores.scores.add(HighScore {
+        name: player.name.clone(),
+        score: simulation.solo_score(0),
+        time_alive: (player.time_alive.as_millis() / 100) as u32,
+        items_collected: player.items_collected,
+    });
+    if high_scores.latest.is_some() {
+        if let Err(e) = config::save(&high_scores.scores, Path::new(HIGH_SCORES_FILE)) {
+            warn!("could not save {}: {}", HIGH_SCORES_FILE, e);
+        }
+    }
+}
+
+fn is_solo_survival(simulation: &Simulation) -> bool {
+    simulation.players.len() == 1 && simulation.rules.mode == GameMode::Survival
+}
+
+fn solo_summary(simulation: &Simulation, high_scores: &HighScoreTable) -> String {
+    let player = &simulation.players[0];
+    let mut lines = vec![
+        format!(
+            "{:.1} s alive, {} items: {} points",
+            player.time_alive.as_secs_f32(),
+            player.items_collected,
+            simulation.solo_score(0)
+        ),
+        String::new(),
+        "High scores".to_string(),
+    ];
+    for (rank, entry) in high_scores.scores.entries().iter().enumerate() {
+        let marker = if high_scores.latest == Some(rank) {
+            ">"
+        } else {
+            " "
+        };
+        lines.push(format!(
+            "{} {}. {} {} ({}.{} s, {} items)",
+            marker,
+            rank + 1,
+            entry.name,
+            entry.score,
+            entry.time_alive / 10,
+            entry.time_alive % 10,
+            entry.items_collected
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Teams to pick from in team mode, with the colour their players' trails
+/// are shades of.
+const TEAMS: [(&str, Srgba); 3] = [("RED", RED), ("BLUE", BLUE), ("GREEN", GREEN)];
+
+/// Trail colour of the `member`th player of a team, alternating between
+/// lighter and darker shades of the team colour.
+fn team_shade(color: Srgba, member: usize) -> Color {
+    let color = Color::from(color);
+    let amount = member.div_ceil(2) as f32 * 0.15;
+    match member % 2 {
+        0 => color.darker(amount),
+        _ => color.lighter(amount),
+    }
+}
+
+fn team_name(team: usize, settings: &GameSettings, simulation: &Simulation) -> String {
+    match settings.teams {
+        true => format!("Team {}", TEAMS[team].0),
+        false => format!("Player {}", simulation.players[team].name),
+    }
+}
+
+fn scoreboard(current_match: &Match, settings: &GameSettings, simulation: &Simulation) -> String {
+    let mut lines = vec![format!(
+        "Round {} - first to {}",
+        current_match.rounds_played(),
+        current_match.target_score
+    )];
+    for team in current_match.standings() {
+        let score = current_match.team_score(team);
+        if settings.teams {
+            let members: Vec<String> = current_match
+                .members(team)
+                .iter()
+                .map(|index| {
+                    format!(
+                        "{} {}",
+                        simulation.players[*index].name,
+                        current_match.scores()[*index]
+                    )
+                })
+                .collect();
+            lines.push(format!(
+                "{}: {} ({})",
+                TEAMS[team].0,
+                score,
+                members.join(", ")
+            ));
+        } else {
+            lines.push(format!("{}: {}", simulation.players[team].name, score));
+        }
+    }
+    lines.join("\n")
+}
+
+fn setup_match_over(
+    mut commands: Commands,
+    settings: Res<GameSettings>,
+    simulation: Res<RoundSimulation>,
+    current_match: Res<CurrentMatch>,
+) {
+    let text = match current_match.winner() {
+        Some(team) => format!(
+            "{} wins the match!",
+            team_name(team, &settings, &simulation)
+        ),
+        None => "The match is over".to_string(),
+    };
+    let text = format!(
+        "{}\n\n{}",
+        text,
+        scoreboard(&current_match, &settings, &simulation)
+    );
+    commands.spawn((
+        Text2d::new(text),
+        Transform::from_translation(Vec3::new(0., 0., 2.)).with_scale(Vec3::new(
+            1. / 512.,
+            1. / 512.,
+            1.,
+        )),
+        TextFont {
+            font_size: 40.0,
+            ..default()
+        },
+    ));
+}
+
+fn update_match_over(mut commands: Commands, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(KeyCode::Space) || keyboard_input.just_pressed(KeyCode::Escape) {
+        commands.set_state(AppState::MainMenu);
+    }
+}
+
+fn cleanup_match_over(mut commands: Commands, query: Query<Entity, With<Text2d>>) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<CurrentMatch>();
+}
+
+fn on_resize_system(
+    mut resize_reader: EventReader<WindowResized>,
+    mut window_size: ResMut<WindowSize>,
+    mut commands: Commands,
+    query: Option<Single<Entity, With<Camera>>>,
+) {
+    if let Some(e) = resize_reader.read().next() {
+        window_size.width = e.width;
+        window_size.height = e.height;
+
+        if let Some(entity) = query.map(|single| single.into_inner()) {
+            commands.entity(entity).despawn();
+        }
+
+        let smallest_dim = window_size.get_smallest_dimension();
+        commands.spawn((
+            Camera2d,
+            Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)).with_scale(Vec3::new(
+                2. / smallest_dim,
+                2. / smallest_dim,
+                1.,
+            )),
+        ));
+    }
+}
+
+#[derive(Component)]
+struct SeedText;
+
+/// Time left in a round with a time limit.
+#[derive(Component)]
+struct CountdownText;
+
+fn setup_countdown(mut commands: Commands, simulation: Res<RoundSimulation>) {
+    if simulation.time_left().is_none() {
+        return;
+    }
+    commands.spawn((
+        CountdownText,
+        Text2d::new(""),
+        Transform::from_translation(Vec3::new(0.8, 0.9, 2.)).with_scale(Vec3::new(
+            1. / 512.,
+            1. / 512.,
+            1.,
+        )),
+        TextFont {
+            font_size: 30.0,
+            ..default()
+        },
+    ));
+}
+
+fn update_countdown(
+    simulation: Res<RoundSimulation>,
+    mut query: Query<&mut Text2d, With<CountdownText>>,
+) {
+    let Some(time_left) = simulation.time_left() else {
+        return;
+    };
+    if let Ok(mut text) = query.get_single_mut() {
+        let seconds = time_left.as_secs_f32().ceil() as u64;
+        text.0 = format!("{}:{:02}", seconds / 60, seconds % 60);
+    }
+}
+
+fn cleanup_countdown(mut commands: Commands, query: Query<Entity, With<CountdownText>>) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Collision counters, the item to put on the field and key help in
+/// practice mode.
+#[derive(Component, Default)]
+struct PracticeText {
+    /// Index of the selected item in `GameRules::items`.
+    item: usize,
+}
+
+fn setup_practice_text(mut commands: Commands, simulation: Res<RoundSimulation>) {
+    if simulation.rules.mode != GameMode::Practice {
+        return;
+    }
+    commands.spawn((
+        PracticeText::default(),
+        Text2d::new(""),
+        Transform::from_translation(Vec3::new(0., -0.9, 2.)).with_scale(Vec3::new(
+            1. / 512.,
+            1. / 512.,
+            1.,
+        )),
+        TextFont {
+            font_size: 20.0,
+            ..default()
+        },
+    ));
+}
+
+fn update_practice_text(
+    simulation: Res<RoundSimulation>,
+    mut query: Query<(&mut Text2d, &PracticeText)>,
+) {
+    if let Ok((mut text, practice)) = query.get_single_mut() {
+        let collisions = simulation
+            .players
+            .iter()
+            .map(|player| format!("{} {}", player.name, player.collisions))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let item = match simulation.rules.items.get(practice.item) {
+            Some(item) => format!(
+                "{} ({}) {}/{}",
+                item.label,
+                item.target.get_text(),
+                practice.item + 1,
+                simulation.rules.items.len()
+            ),
+            None => "none".to_string(),
+        };
+        text.0 = format!(
+            "Collisions: {}\nItem: {}  Tab: next item  Enter: put on field\nBackspace: clear board  Speed: {}%",
+            collisions,
+            item,
+            (simulation.rules.practice_speed * 100.).round()
+        );
+    }
+}
+
+fn cleanup_practice_text(mut commands: Commands, query: Query<Entity, With<PracticeText>>) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// In practice mode, Tab selects the next item of the item set, Enter puts
+/// the selected one on the field and Backspace clears the board.
+fn update_practice(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut simulation: ResMut<RoundSimulation>,
+    mut query: Query<&mut PracticeText>,
+) {
+    let Ok(mut practice) = query.get_single_mut() else {
+        return;
+    };
+    let item_count = simulation.rules.items.len();
+    if keyboard_input.just_pressed(KeyCode::Tab) && item_count > 0 {
+        practice.item = (practice.item + 1) % item_count;
+    }
+    if keyboard_input.just_pressed(KeyCode::Enter) {
+        if let Some(item) = simulation.rules.items.get(practice.item).cloned() {
+            simulation.add_item(item);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Backspace) {
+        simulation.clear_arena();
+    }
+}
+
+fn setup_seed_text(mut commands: Commands, simulation: Res<RoundSimulation>) {
+    commands.spawn((
+        SeedText,
+        Text2d::new(format!("Seed: {}", simulation.seed())),
+        Transform::from_translation(Vec3::new(0., 0.9, 2.)).with_scale(Vec3::new(
+            1. / 512.,
+            1. / 512.,
+            1.,
+        )),
+        TextFont {
+            font_size: 20.0,
+            ..default()
+        },
+    ));
+}
+
+fn cleanup_seed_text(mut commands: Commands, query: Query<Entity, With<SeedText>>) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+}
+
+fn update_round_start(mut commands: Commands, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        commands.set_state(AppState::RoundActive);
+    }
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        commands.set_state(AppState::MainMenu);
+    }
+}
+
+fn update_round_over(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    recorder: Option<Res<ReplayRecorder>>,
+    current_match: Res<CurrentMatch>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        if current_match.winner().is_some() {
+            commands.set_state(AppState::MatchOver);
+        } else {
+            commands.set_state(AppState::RoundStart);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyR) {
+        if let Some(recorder) = recorder {
+            start_replay(&mut commands, recorder.0.clone());
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        commands.set_state(AppState::MainMenu);
+    }
+}
+
+fn cleanup_round_over(mut commands: Commands, query: Query<Entity, With<Text2d>>) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn setup_in_game(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    settings: Res<GameSettings>,
+    rules: Res<Rules>,
+    roster: Res<Roster>,
+    current_match: Res<CurrentMatch>,
+    mut images: ResMut<Assets<Image>>,
+    window_size: Res<WindowSize>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+) {
+    fixed_time.set_timestep_hz(settings.tick_rate);
+
     let smallest_dim = window_size.get_smallest_dimension();
     let texture_size = if smallest_dim > 512. {
         smallest_dim as u32
     } else {
         512
     };
+    let seed = match settings.seed {
+        Some(seed) => current_match.round_seed(seed),
+        None => rand::thread_rng().gen(),
+    };
+    let mut simulation = Simulation::new(texture_size as usize, seed, rules.0.clone());
+    if let Some(path) = &settings.map {
+        match Map::load(path) {
+            Ok(map) => simulation.set_map(&map),
+            Err(e) => warn!("could not load map {}: {}", path.display(), e),
+        }
+    }
+    spawn_arena(
+        &simulation,
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        &mut images,
+    );
+
+    let teams = roster.player_teams(settings.teams);
+    let slots = roster.slots.iter().filter(|slot| slot.enabled);
+    for (index, (slot, team)) in slots.zip(&teams).enumerate() {
+        let color = match settings.teams {
+            true => {
+                let member = teams[..index].iter().filter(|t| *t == team).count();
+                team_shade(TEAMS[*team].1, member)
+            }
+            false => slot.color,
+        };
+        spawn_player(
+            slot.name.clone(),
+            color,
+            *team,
+            slot.input_source(),
+            &mut simulation,
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+        );
+    }
+
+    commands.insert_resource(ReplayRecorder(Replay::new(&simulation, settings.tick_rate)));
+    commands.insert_resource(RoundSimulation(simulation));
+}
+
+fn spawn_arena(
+    simulation: &Simulation,
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    images: &mut ResMut<Assets<Image>>,
+) {
+    let texture_size = simulation.arena.size() as u32;
     let texture = Image::new_fill(
         Extent3d {
             width: texture_size,
@@ -261,7 +1625,7 @@ fn setup_in_game(
             depth_or_array_layers: 1,
         },
         TextureDimension::D2,
-        &vec![0x00; (texture_size * texture_size * 4) as usize],
+        simulation.arena.data(),
         TextureFormat::Rgba8UnormSrgb,
         RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
     );
@@ -287,242 +1651,81 @@ fn setup_in_game(
             .with_scale(Vec3::splat(2.))
             .with_translation(Vec3::new(0., 0., -10.)),
     ));
-
-    if settings.number_of_players >= 1 {
-        spawn_player(
-            "RED".to_string(),
-            Color::from(RED),
-            (KeyCode::ArrowLeft, KeyCode::ArrowRight),
-            &mut commands,
-            &mut meshes,
-            &mut materials,
-        );
-    }
-    if settings.number_of_players >= 2 {
-        spawn_player(
-            "GREEN".to_string(),
-            Color::from(GREEN),
-            (KeyCode::KeyA, KeyCode::KeyD),
-            &mut commands,
-            &mut meshes,
-            &mut materials,
-        );
-    }
-    if settings.number_of_players >= 3 {
-        spawn_player(
-            "BLUE".to_string(),
-            Color::from(BLUE),
-            (KeyCode::KeyV, KeyCode::KeyN),
-            &mut commands,
-            &mut meshes,
-            &mut materials,
-        );
-    }
-
-    commands.insert_resource(ItemSpawnState::new());
 }
 
-fn move_players_a_bit(
-    query: Query<(&Transform, &Player)>,
-    mut images: ResMut<Assets<Image>>,
-    trail_texture: Res<TrailTexture>,
-) {
-    let texture_handle = &trail_texture.image_handle;
-    let texture = images.get_mut(texture_handle).unwrap();
-
-    for (transform, player) in &query {
-        let pos = transform.translation;
-        let pos_before = pos - player.dir * 10. / 256.;
-
-        draw_trail(
-            pos_before,
-            player.dir,
-            pos,
-            player.dir,
-            2.5 / 256.,
-            texture,
-            player.color,
-        );
-    }
+fn move_players_a_bit(mut simulation: ResMut<RoundSimulation>) {
+    simulation.move_players_a_bit();
 }
 
+#[allow(clippy::too_many_arguments)]
 fn spawn_player(
     name: String,
     color: Color,
-    steer_keys: (KeyCode, KeyCode),
+    team: usize,
+    input_source: InputSource,
+    simulation: &mut Simulation,
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<ColorMaterial>>,
 ) {
-    let (position, direction) = random_position_and_direction();
-    commands.spawn((
-        Player::new(name, color, direction, steer_keys),
-        Mesh2d(meshes.add(Circle::default())),
-        MeshMaterial2d(materials.add(Color::from(YELLOW))),
-        Transform::default()
-            .with_scale(Vec3::splat(5. / 256.))
-            .with_translation(position),
-    ));
+    let index = simulation.add_team_player(name, color.to_srgba().to_u8_array(), team);
+    let entity = spawn_player_head(index, simulation, commands, meshes, materials);
+    commands.entity(entity).insert(input_source);
+    if let InputSource::Bot(difficulty) = input_source {
+        commands
+            .entity(entity)
+            .insert(BotController(Bot::new(difficulty)));
+    }
 }
 
-fn random_position_and_direction() -> (Vec3, Vec3) {
-    let mut rng = rand::thread_rng();
-
-    let position = Vec3::new(rng.gen_range(-0.8..0.8), rng.gen_range(-0.8..0.8), 0.);
-
-    let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.).normalize();
-
-    (position, direction)
+fn spawn_player_head(
+    index: usize,
+    simulation: &Simulation,
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+) -> Entity {
+    commands
+        .spawn((
+            Player { index },
+            Mesh2d(meshes.add(Circle::default())),
+            MeshMaterial2d(materials.add(Color::from(YELLOW))),
+            Transform::default()
+                .with_scale(Vec3::splat(5. / 256.))
+                .with_translation(simulation.players[index].position),
+        ))
+        .id()
 }
 
+#[allow(clippy::type_complexity)]
 fn cleanup_in_game(
     mut commands: Commands,
     query: Query<Entity, Or<(With<Mesh2d>, With<Sprite>)>>,
     mut images: ResMut<Assets<Image>>,
     trail_texture: Option<Res<TrailTexture>>,
 ) {
+    // Recursive, so item bubbles take their labels with them.
     for entity in &query {
-        commands.entity(entity).despawn();
+        commands.entity(entity).despawn_recursive();
     }
     if let Some(trail_texture) = trail_texture {
-        images.remove(&trail_texture.image_handle);
-    }
-}
-
-#[derive(Component)]
-struct Player {
-    name: String,
-    dir: Vec3,
-    color: Color,
-    steer_keys: (KeyCode, KeyCode),
-    alive: bool,
-    gap_state: PlayerGapState,
-    item_effects: Vec<(ItemEffectIndividual, Timer)>,
-}
-
-impl Player {
-    fn new(name: String, color: Color, dir: Vec3, steer_keys: (KeyCode, KeyCode)) -> Self {
-        Player {
-            name,
-            dir,
-            color,
-            steer_keys,
-            alive: true,
-            gap_state: PlayerGapState::new(),
-            item_effects: Vec::new(),
-        }
-    }
-
-    fn speed_mod(&self) -> i64 {
-        let count_speed = self
-            .item_effects
-            .iter()
-            .filter(|(effect, _)| *effect == ItemEffectIndividual::Speed)
-            .count();
-        let count_slow = self
-            .item_effects
-            .iter()
-            .filter(|(effect, _)| *effect == ItemEffectIndividual::Slowness)
-            .count();
-
-        count_speed as i64 - count_slow as i64
-    }
-
-    fn thickness_mod(&self) -> i64 {
-        let count_thick = self
-            .item_effects
-            .iter()
-            .filter(|(effect, _)| *effect == ItemEffectIndividual::Thick)
-            .count();
-        let count_thin = self
-            .item_effects
-            .iter()
-            .filter(|(effect, _)| *effect == ItemEffectIndividual::Thin)
-            .count();
-
-        count_thick as i64 - count_thin as i64
-    }
-
-    fn is_free_flying(&self) -> bool {
-        self.item_effects
-            .iter()
-            .filter(|(effect, _)| *effect == ItemEffectIndividual::FreeFlying)
-            .next()
-            .is_some()
-    }
-
-    fn is_steering_inverse(&self) -> bool {
-        self.item_effects
-            .iter()
-            .filter(|(effect, _)| *effect == ItemEffectIndividual::InverseSteer)
-            .next()
-            .is_some()
-    }
-
-    fn get_current_steer_keys(&self) -> (KeyCode, KeyCode) {
-        if self.is_steering_inverse() {
-            (self.steer_keys.1, self.steer_keys.0)
-        } else {
-            self.steer_keys
-        }
-    }
-
-    fn update_item_effects(&mut self, delta: Duration) {
-        let mut indices_to_remove = vec![];
-        for (index, tuple) in self.item_effects.iter_mut().enumerate() {
-            tuple.1.tick(delta);
-            if tuple.1.finished() {
-                indices_to_remove.push(index);
-            }
-        }
-        for index in indices_to_remove.into_iter().rev() {
-            self.item_effects.remove(index);
-        }
-    }
-
-    fn add_effect(&mut self, effect: ItemEffectIndividual) {
-        self.item_effects
-            .push((effect, Timer::new(Duration::from_secs(5), TimerMode::Once)));
+        images.remove(&trail_texture.image_handle);
     }
 }
 
-struct PlayerGapState {
-    gapping: bool,
-    timer: Timer,
+/// Links a player entity to its slot in the `Simulation`.
+#[derive(Component)]
+struct Player {
+    index: usize,
 }
 
-impl PlayerGapState {
-    fn new() -> Self {
-        Self {
-            gapping: false,
-            timer: PlayerGapState::random_timer(),
-        }
-    }
-
-    fn random_timer() -> Timer {
-        let mut rng = rand::thread_rng();
-        Timer::new(
-            Duration::from_millis(rng.gen_range(1000..5000)),
-            TimerMode::Once,
-        )
-    }
-
-    fn gap_timer() -> Timer {
-        Timer::new(Duration::from_millis(300), TimerMode::Once)
-    }
+#[derive(Component, Deref, DerefMut)]
+struct BotController(Bot);
 
-    fn update(&mut self, delta: Duration) {
-        self.timer.tick(delta);
-        if self.timer.finished() {
-            if self.gapping {
-                self.gapping = false;
-                self.timer = PlayerGapState::random_timer();
-            } else {
-                self.gapping = true;
-                self.timer = PlayerGapState::gap_timer();
-            }
-        }
-    }
+/// Marks the entity displaying the field item with the given id.
+#[derive(Component)]
+struct ItemBubble {
+    id: u64,
 }
 
 #[derive(Resource)]
@@ -530,481 +1733,493 @@ struct TrailTexture {
     image_handle: Handle<Image>,
 }
 
-#[derive(Component)]
-enum Item {
-    SelfEffect(ItemEffectIndividual),
-    OthersEffect(ItemEffectIndividual),
-    GlobalEffect(ItemEffectGlobal),
-}
+#[derive(Resource, Deref, DerefMut)]
+struct RoundSimulation(Simulation);
 
-impl Item {
-    fn get_random() -> Self {
-        let mut rng = rand::thread_rng();
+#[derive(Resource, Deref, DerefMut)]
+struct CurrentMatch(Match);
 
-        match rng.gen_range(0..3) {
-            0 => Self::SelfEffect(ItemEffectIndividual::get_random_selfeffect()),
-            1 => Self::OthersEffect(ItemEffectIndividual::get_random_otherseffect()),
-            2 => Self::GlobalEffect(ItemEffectGlobal::get_random()),
-            _ => panic!("item randomizer is broken"),
-        }
-    }
+/// Records the inputs of the running round.
+#[derive(Resource, Deref, DerefMut)]
+struct ReplayRecorder(Replay);
 
-    fn get_text(&self) -> String {
-        match self {
-            Item::SelfEffect(e) => e.get_text(),
-            Item::OthersEffect(e) => e.get_text(),
-            Item::GlobalEffect(e) => e.get_text(),
-        }
+fn update_round_active(mut commands: Commands, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        commands.set_state(AppState::MainMenu);
     }
 }
 
+/// Advances the simulation by one tick. Runs in `FixedUpdate`, so `time`
+/// is the fixed timestep and the outcome does not depend on the frame rate.
 fn game_logic(
-    mut query: Query<(
-        &mut Transform,
-        &mut Player,
-        &mut MeshMaterial2d<ColorMaterial>,
-    )>,
+    mut query: Query<(&Player, &InputSource, Option<&mut BotController>)>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepads: Query<&Gamepad>,
     time: Res<Time>,
-    mut commands: Commands,
+    mut simulation: ResMut<RoundSimulation>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    // The state changes in the next frame; until then, ticks caught up in
+    // this one must not play on.
+    if simulation.is_round_over() {
+        return;
+    }
+    let mut inputs = vec![SteerInput::default(); simulation.players.len()];
+    for (player, input_source, bot) in &mut query {
+        inputs[player.index] = match bot {
+            Some(mut bot) => bot.steer(&simulation, player.index, time.delta()),
+            None => input_source.steer_input(&keyboard_input, &gamepads),
+        };
+    }
+
+    recorder.record(&inputs);
+    simulation.tick(&inputs, time.delta());
+}
+
+/// Heads are interpolated between the last two ticks, so they move smoothly
+/// even when the frame rate is higher than the tick rate.
+fn draw_simulation(
+    mut query: Query<(&Player, &mut Transform, &MeshMaterial2d<ColorMaterial>)>,
+    simulation: Res<RoundSimulation>,
+    fixed_time: Res<Time<Fixed>>,
     mut images: ResMut<Assets<Image>>,
     trail_texture: Res<TrailTexture>,
     mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::Escape) {
-        commands.set_state(AppState::MainMenu);
+    let overstep = fixed_time.overstep_fraction();
+    for (player, mut transform, _) in &mut query {
+        let state = &simulation.players[player.index];
+        transform.translation = state.previous_position.lerp(state.position, overstep);
     }
 
-    for (mut transform, mut player, mut material_handle) in &mut query {
-        if !player.alive {
-            continue;
-        }
-
-        let dir_before = player.dir;
-        let (left_key, right_key) = player.get_current_steer_keys();
-        if keyboard_input.pressed(left_key) {
-            let rotation =
-                Quat::from_rotation_z(std::f32::consts::PI / 60.0 / 0.016 * time.delta_secs());
-            player.dir = rotation.mul_vec3(player.dir);
-        }
-        if keyboard_input.pressed(right_key) {
-            let rotation =
-                Quat::from_rotation_z(-std::f32::consts::PI / 60.0 / 0.016 * time.delta_secs());
-            player.dir = rotation.mul_vec3(player.dir);
-        }
-
-        let texture_handle = &trail_texture.image_handle;
-        let texture = images.get_mut(texture_handle).unwrap();
-
-        // Map the worjd position to texture space
-        let size = texture.size().x as usize;
-
-        let pos_before = transform.translation;
+    if !simulation.is_changed() {
+        return;
+    }
 
-        let player_base_speed = 60. / 256.;
-        let modifier = 2f32.powf(player.speed_mod() as f32);
-        let player_speed = player_base_speed * modifier;
-        transform.translation += player.dir * time.delta_secs() * player_speed;
+    if let Some(texture) = images.get_mut(&trail_texture.image_handle) {
+        texture.data.copy_from_slice(simulation.arena.data());
+    }
 
-        let player_base_radius = 2.5 / 256.;
-        let modifier = 2f32.powf(player.thickness_mod() as f32);
-        let player_radius = player_base_radius * modifier;
-        transform.scale = Vec3::splat(player_radius * 2.);
+    for (player, mut transform, material_handle) in &mut query {
+        let state = &simulation.players[player.index];
+        transform.scale = Vec3::splat(state.radius(&simulation.rules) * 2.);
 
         if let Some(material) = materials.get_mut(&material_handle.0) {
-            material.color = match player.is_steering_inverse() {
-                true => Color::from(BLUE),
-                false => Color::from(YELLOW),
-            };
-        }
-
-        for vec in get_collision_points(transform.translation, player.dir, player_radius) {
-            if let Some((x, y)) = game_to_texture_coord(vec, size) {
-                let index = (y * size + x) * 4; // RGBA
-                let alpha = texture.data[index + 3];
-                if alpha != 0 && !player.is_free_flying() {
-                    // something was hit
-                    player.alive = false;
-                }
+            material.color = if !state.collision_flash.is_zero() {
+                Color::from(RED)
+            } else if state.is_steering_inverse() {
+                Color::from(BLUE)
             } else {
-                // player is out of bounds
-                player.alive = false;
-            }
-        }
-
-        player.gap_state.update(time.delta());
-        if !player.gap_state.gapping && !player.is_free_flying() {
-            draw_trail(
-                pos_before,
-                dir_before,
-                transform.translation,
-                player.dir,
-                player_radius,
-                texture,
-                player.color,
-            );
+                Color::from(YELLOW)
+            };
         }
     }
 }
 
-fn game_to_texture_vec(game_coord: Vec3, texture_size: usize) -> Vec3 {
-    let x = (game_coord.x + 1.0) * texture_size as f32 / 2.0;
-    let y = (game_coord.y - 1.0) * texture_size as f32 / -2.0;
-
-    Vec3::new(x, y, game_coord.z)
-}
-
-fn game_to_texture_coord(game_coord: Vec3, texture_size: usize) -> Option<(usize, usize)> {
-    let mapped_vec = game_to_texture_vec(game_coord, texture_size);
-    let ix = mapped_vec.x as isize;
-    let iy = mapped_vec.y as isize;
-
-    if ix < 0 || ix >= texture_size as isize || iy < 0 || iy >= texture_size as isize {
-        return None;
+fn check_round_over(mut commands: Commands, simulation: Res<RoundSimulation>) {
+    if simulation.is_round_over() {
+        commands.set_state(AppState::RoundOver);
     }
-
-    Some((ix as usize, iy as usize))
-}
-
-fn get_collision_points(translation: Vec3, dir: Vec3, radius: f32) -> Vec<Vec3> {
-    let rotation_left = Quat::from_rotation_z(std::f32::consts::PI / 3.);
-    let rotation_right = Quat::from_rotation_z(-std::f32::consts::PI / 3.);
-    let front = translation + radius * dir;
-    let left = translation + radius * rotation_left.mul_vec3(dir);
-    let right = translation + radius * rotation_right.mul_vec3(dir);
-    vec![front, left, right]
 }
 
-fn draw_trail(
-    translation_before: Vec3,
-    dir_before: Vec3,
-    translation_now: Vec3,
-    dir_now: Vec3,
-    radius: f32,
-    texture: &mut Image,
-    color: Color,
+fn draw_items(
+    mut commands: Commands,
+    simulation: Res<RoundSimulation>,
+    mut query: Query<(Entity, &ItemBubble, &mut Visibility)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
-    let size = texture.size().x as usize;
-    let rotation_90deg = Quat::from_rotation_z(std::f32::consts::PI / 2.);
-
-    let dir_rot_before = rotation_90deg.mul_vec3(dir_before);
-    let left_before = translation_before + radius * dir_rot_before;
-    let right_before = translation_before - radius * dir_rot_before;
-
-    let dir_rot_now = rotation_90deg.mul_vec3(dir_now);
-    let left_now = translation_now + radius * dir_rot_now;
-    let right_now = translation_now - radius * dir_rot_now;
-
-    let quad = [
-        game_to_texture_vec(left_now, size),
-        game_to_texture_vec(right_now, size),
-        game_to_texture_vec(left_before, size),
-        game_to_texture_vec(right_before, size),
-    ];
-
-    let coords_to_draw = get_all_coordinates_in_quad(quad);
-    for (x, y) in coords_to_draw {
-        let index = (y * size + x) * 4; // RGBA
-        let color = color.to_srgba();
-        texture.data[index..index + 4].copy_from_slice(&[
-            (color.red * 255.) as u8,
-            (color.green * 255.) as u8,
-            (color.blue * 255.) as u8,
-            (color.alpha * 255.) as u8,
-        ]);
-    }
-}
-
-fn check_round_over(mut commands: Commands, query: Query<&Player>) {
-    let mut players_alive = 0;
-    if query.iter().count() == 1 {
+    if !simulation.is_changed() {
         return;
     }
-    for player in &query {
-        if player.alive {
-            players_alive += 1;
-        }
-    }
-    if players_alive <= 1 {
-        commands.set_state(AppState::RoundOver);
-    }
-}
-
-fn get_all_coordinates_in_quad(quad: [Vec3; 4]) -> HashSet<(usize, usize)> {
-    let x_min = quad.iter().map(|v| v.x as usize).min().unwrap();
-    let y_min = quad.iter().map(|v| v.y as usize).min().unwrap();
-    let x_max = quad.iter().map(|v| v.x as usize).max().unwrap();
-    let y_max = quad.iter().map(|v| v.y as usize).max().unwrap();
 
-    let mut points = HashSet::new();
+    let mut displayed = HashSet::new();
+    for (entity, bubble, mut visibility) in &mut query {
+        let Some(field_item) = simulation.items.iter().find(|item| item.id == bubble.id) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+        displayed.insert(bubble.id);
+        let hidden = field_item.is_expiring(&simulation.rules)
+            && field_item
+                .time_left
+                .is_some_and(|time_left| time_left.as_millis() / ITEM_BLINK_INTERVAL_MS % 2 == 1);
+        *visibility = match hidden {
+            true => Visibility::Hidden,
+            false => Visibility::Inherited,
+        };
+    }
 
-    for x in x_min..=x_max {
-        for y in y_min..=y_max {
-            let vec = Vec3::new(x as f32, y as f32, 0.);
-            if is_point_inside_of_quad(vec, quad) {
-                points.insert((x, y));
-            }
+    for field_item in &simulation.items {
+        if !displayed.contains(&field_item.id) {
+            spawn_item(field_item, &mut commands, &mut meshes, &mut materials);
         }
     }
-
-    points
 }
 
-fn is_point_inside_of_quad(p: Vec3, quad: [Vec3; 4]) -> bool {
-    let tria0 = [quad[0], quad[1], quad[2]];
-    let tria1 = [quad[1], quad[2], quad[3]];
+fn spawn_item(
+    field_item: &FieldItem,
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+) {
+    let item_text = field_item.item.label.clone();
+    let [r, g, b] = field_item.item.color;
+    let item_color = Color::srgb_u8(r, g, b);
+    let entity = commands
+        .spawn((
+            ItemBubble { id: field_item.id },
+            Mesh2d(meshes.add(Circle::default())),
+            MeshMaterial2d(materials.add(Color::srgba(0., 0., 0., 0.))),
+            Transform::default()
+                .with_translation(field_item.position)
+                .with_scale(Vec3::splat(1.)),
+        ))
+        .id();
 
-    is_point_inside_of_triangle(p, tria0) || is_point_inside_of_triangle(p, tria1)
+    commands.entity(entity).with_children(|parent| {
+        parent.spawn((
+            Text2d::new(item_text),
+            TextFont {
+                font_size: 15.0,
+                ..default()
+            },
+            Transform::from_translation(Vec3::new(0., 0., 0.2)).with_scale(Vec3::splat(1. / 280.)),
+        ));
+        parent.spawn((
+            Mesh2d(meshes.add(Circle::default())),
+            MeshMaterial2d(materials.add(item_color)),
+            Transform::from_translation(Vec3::new(0., 0., 0.1)).with_scale(Vec3::splat(40. / 256.)),
+        ));
+    });
 }
 
-fn is_point_inside_of_triangle(p: Vec3, mut triangle: [Vec3; 3]) -> bool {
-    let area = triangle_area(triangle);
-    let mut parts_area = 0.0;
-    for i in 0..triangle.len() {
-        let replaced = triangle[i];
-        triangle[i] = p;
-        parts_area += triangle_area(triangle);
-        triangle[i] = replaced;
+fn save_replay(recorder: Res<ReplayRecorder>) {
+    match recorder.save_numbered(Path::new(REPLAY_DIR)) {
+        Ok(path) => info!("replay saved to {}", path.display()),
+        Err(e) => warn!("could not save replay: {}", e),
     }
-
-    parts_area / area < 1.05
-}
-
-fn triangle_area(triangle: [Vec3; 3]) -> f32 {
-    0.5 * (triangle[0].x * triangle[1].y
-        + triangle[1].x * triangle[2].y
-        + triangle[2].x * triangle[0].y
-        - triangle[0].y * triangle[1].x
-        - triangle[1].y * triangle[2].x
-        - triangle[2].y * triangle[0].x)
-        .abs()
 }
 
+/// Plays a recorded round. The recorded inputs are fed into a simulation
+/// recreated from the replay's seed and settings.
 #[derive(Resource)]
-struct ItemSpawnState {
-    time_to_next_spawn: Timer,
+struct ReplayPlayback {
+    replay: Replay,
+    inputs: Vec<Vec<SteerInput>>,
+    tick: usize,
+    paused: bool,
+    speed: u32,
+    /// Ticks to advance while paused.
+    steps: u32,
 }
 
-impl ItemSpawnState {
-    fn new() -> Self {
-        ItemSpawnState {
-            time_to_next_spawn: ItemSpawnState::random_timer(),
+impl ReplayPlayback {
+    fn new(replay: Replay) -> Self {
+        ReplayPlayback {
+            inputs: replay.tick_inputs(),
+            replay,
+            tick: 0,
+            paused: false,
+            speed: 1,
+            steps: 0,
         }
     }
 
-    fn update(&mut self, delta: Duration) -> bool {
-        self.time_to_next_spawn.tick(delta);
-        if self.time_to_next_spawn.finished() {
-            self.time_to_next_spawn = ItemSpawnState::random_timer();
-            true
+    fn ticks_to_advance(&mut self) -> u32 {
+        if self.paused {
+            std::mem::take(&mut self.steps)
         } else {
-            false
+            self.speed
         }
     }
-
-    fn random_timer() -> Timer {
-        let mut rng = rand::thread_rng();
-        Timer::new(
-            Duration::from_millis(rng.gen_range(3000..6000)),
-            TimerMode::Once,
-        )
-    }
-
-    fn random_position() -> Vec3 {
-        let mut rng = rand::thread_rng();
-
-        Vec3::new(rng.gen_range(-0.8..0.8), rng.gen_range(-0.8..0.8), -3.)
-    }
-}
-
-#[derive(PartialEq, Eq, Clone, Copy)]
-enum ItemEffectIndividual {
-    Speed,
-    Slowness,
-    Thin,
-    Thick,
-    FreeFlying,
-    InverseSteer,
 }
 
-impl ItemEffectIndividual {
-    fn get_random_selfeffect() -> Self {
-        let mut rng = rand::thread_rng();
-
-        match rng.gen_range(0..6) {
-            0 => Self::Speed,
-            1 => Self::Slowness,
-            2 => Self::Thin,
-            3 => Self::Thick,
-            4 => Self::FreeFlying,
-            5 => Self::InverseSteer,
-            _ => panic!("item randomizer is broken"),
+#[derive(Component)]
+struct ReplayText;
+
+/// Switches to playing `replay`, if its simulation can be recreated.
+fn start_replay(commands: &mut Commands, replay: Replay) {
+    match replay.simulation() {
+        Ok(simulation) => {
+            commands.insert_resource(RoundSimulation(simulation));
+            commands.insert_resource(ReplayPlayback::new(replay));
+            commands.set_state(AppState::Replay);
         }
+        Err(e) => warn!("could not play replay: {}", e),
     }
+}
 
-    fn get_random_otherseffect() -> Self {
-        let mut rng = rand::thread_rng();
-
-        match rng.gen_range(0..4) {
-            0 => Self::Speed,
-            1 => Self::Slowness,
-            2 => Self::Thin,
-            3 => Self::Thick,
-            _ => panic!("item randomizer is broken"),
-        }
+fn setup_replay(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+    playback: Res<ReplayPlayback>,
+    simulation: Res<RoundSimulation>,
+) {
+    fixed_time.set_timestep(playback.replay.tick_duration());
+
+    spawn_arena(
+        &simulation,
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        &mut images,
+    );
+    for index in 0..simulation.players.len() {
+        spawn_player_head(
+            index,
+            &simulation,
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+        );
     }
 
-    fn get_text(&self) -> String {
-        match self {
-            Self::Speed => "fast",
-            Self::Slowness => "slow",
-            Self::Thin => "thin",
-            Self::Thick => "thick",
-            Self::FreeFlying => "free",
-            Self::InverseSteer => "<-->",
-        }
-        .to_string()
-    }
+    commands.spawn((
+        ReplayText,
+        Text2d::new(""),
+        Transform::from_translation(Vec3::new(0., 0.9, 2.)).with_scale(Vec3::new(
+            1. / 512.,
+            1. / 512.,
+            1.,
+        )),
+        TextFont {
+            font_size: 20.0,
+            ..default()
+        },
+    ));
 }
 
-#[derive(PartialEq, Eq, Clone, Copy)]
-enum ItemEffectGlobal {
-    Clear,
-    MoreItems,
+fn cleanup_replay(mut commands: Commands, query: Query<Entity, With<ReplayText>>) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<ReplayPlayback>();
 }
 
-impl ItemEffectGlobal {
-    fn get_random() -> Self {
-        let mut rng = rand::thread_rng();
-
-        match rng.gen_range(0..2) {
-            0 => Self::Clear,
-            1 => Self::MoreItems,
-            _ => panic!("item randomizer is broken"),
-        }
+/// Space pauses, F toggles fast-forward and Period steps a single tick while
+/// paused.
+fn update_replay(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut query: Query<&mut Text2d, With<ReplayText>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        commands.set_state(AppState::MainMenu);
+    }
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyF) {
+        playback.speed = if playback.speed == 1 { 4 } else { 1 };
+    }
+    if keyboard_input.just_pressed(KeyCode::Period) && playback.paused {
+        playback.steps += 1;
     }
 
-    fn get_text(&self) -> String {
-        match self {
-            Self::Clear => "clear",
-            Self::MoreItems => "more",
-        }
-        .to_string()
+    if let Ok(mut text) = query.get_single_mut() {
+        let status = if playback.paused {
+            "paused".to_string()
+        } else {
+            format!("x{}", playback.speed)
+        };
+        text.0 = format!(
+            "Replay {}/{} ({})",
+            playback.tick,
+            playback.inputs.len(),
+            status
+        );
     }
 }
 
-fn spawn_items(
-    mut commands: Commands,
-    mut spawn_state: ResMut<ItemSpawnState>,
+fn replay_logic(
     time: Res<Time>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut simulation: ResMut<RoundSimulation>,
+    mut playback: ResMut<ReplayPlayback>,
 ) {
-    if spawn_state.update(time.delta()) {
-        spawn_item(&mut commands, &mut meshes, &mut materials);
+    for _ in 0..playback.ticks_to_advance() {
+        let Some(inputs) = playback.inputs.get(playback.tick) else {
+            return;
+        };
+        simulation.tick(inputs, time.delta());
+        playback.tick += 1;
     }
 }
 
-fn spawn_item(
-    commands: &mut Commands,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<ColorMaterial>>,
+/// Map being painted in the map editor, and the texture showing it.
+#[derive(Resource)]
+struct MapEditor {
+    map: Map,
+    brush: MapPixel,
+    /// Brush radius in map pixels.
+    brush_radius: i64,
+    image_handle: Handle<Image>,
+}
+
+#[derive(Component)]
+struct MapEditorEntity;
+
+#[derive(Component)]
+struct MapEditorText;
+
+/// First unused file name for a new map in `MAP_DIR`.
+fn new_map_path() -> PathBuf {
+    (0..)
+        .map(|number| Path::new(MAP_DIR).join(format!("map-{:02}.png", number)))
+        .find(|path| !path.exists())
+        .unwrap_or_else(|| Path::new(MAP_DIR).join("map.png"))
+}
+
+/// Opens the map picked in the menu, or a new empty one.
+fn setup_map_editor(
+    mut commands: Commands,
+    settings: Res<GameSettings>,
+    mut images: ResMut<Assets<Image>>,
 ) {
-    let item = Item::get_random();
-    let item_text = item.get_text();
-    let item_color = match item {
-        Item::SelfEffect(_) => Color::from(GREEN),
-        Item::OthersEffect(_) => Color::from(RED),
-        Item::GlobalEffect(_) => Color::from(BLUE),
+    let map = match &settings.map {
+        Some(path) => Map::load(path).unwrap_or_else(|e| {
+            warn!("could not load map {}: {}", path.display(), e);
+            Map::new(new_map_path(), NEW_MAP_SIZE)
+        }),
+        None => Map::new(new_map_path(), NEW_MAP_SIZE),
     };
-    let entity = commands
-        .spawn((
-            item,
-            Mesh2d(meshes.add(Circle::default())),
-            MeshMaterial2d(materials.add(Color::srgba(0., 0., 0., 0.))),
-            Transform::default()
-                .with_translation(ItemSpawnState::random_position())
-                .with_scale(Vec3::splat(1.)),
-        ))
-        .id();
 
-    commands.entity(entity).with_children(|parent| {
-        parent.spawn((
-            Text2d::new(item_text),
-            TextFont {
-                font_size: 15.0,
-                ..default()
-            },
-            Transform::from_translation(Vec3::new(0., 0., 0.2)).with_scale(Vec3::splat(1. / 280.)),
-        ));
-        parent.spawn((
-            Mesh2d(meshes.add(Circle::default())),
-            MeshMaterial2d(materials.add(item_color)),
-            Transform::from_translation(Vec3::new(0., 0., 0.1)).with_scale(Vec3::splat(40. / 256.)),
-        ));
+    let mut texture = Image::new_fill(
+        Extent3d {
+            width: map.width(),
+            height: map.height(),
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 0],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+    );
+    texture.data.copy_from_slice(map.data());
+    let image_handle = images.add(texture);
+    commands.spawn((
+        MapEditorEntity,
+        Sprite {
+            image: image_handle.clone(),
+            ..Default::default()
+        },
+        Transform {
+            translation: Vec3::new(0.0, 0.0, -2.0),
+            scale: Vec3::new(2. / map.width() as f32, 2. / map.height() as f32, 1.),
+            ..Default::default()
+        },
+    ));
+    commands.spawn((
+        MapEditorEntity,
+        MapEditorText,
+        Text2d::new(""),
+        Transform::from_translation(Vec3::new(0., 0.9, 2.)).with_scale(Vec3::new(
+            1. / 512.,
+            1. / 512.,
+            1.,
+        )),
+        TextFont {
+            font_size: 20.0,
+            ..default()
+        },
+    ));
+
+    commands.insert_resource(MapEditor {
+        map,
+        brush: MapPixel::Wall,
+        brush_radius: 3,
+        image_handle,
     });
 }
 
-fn update_player_item_effects(mut query: Query<&mut Player>, time: Res<Time>) {
-    for mut player in &mut query {
-        player.update_item_effects(time.delta());
+fn cleanup_map_editor(mut commands: Commands, query: Query<Entity, With<MapEditorEntity>>) {
+    for entity in &query {
+        commands.entity(entity).despawn();
     }
+    commands.remove_resource::<MapEditor>();
 }
 
-fn item_collection(
+/// The left mouse button paints with the brush and the right one erases.
+/// Digits pick the brush, brackets change its size and S saves the map.
+#[allow(clippy::too_many_arguments)]
+fn update_map_editor(
     mut commands: Commands,
-    mut player_query: Query<(&mut Player, &Transform)>,
-    item_query: Query<(Entity, &Item, &Transform)>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut editor: ResMut<MapEditor>,
+    mut settings: ResMut<GameSettings>,
     mut images: ResMut<Assets<Image>>,
-    trail_texture: Res<TrailTexture>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut query: Query<&mut Text2d, With<MapEditorText>>,
 ) {
-    let mut others_effects: Vec<(String, ItemEffectIndividual)> = Vec::new();
-    for (mut player, player_transform) in &mut player_query {
-        let player_translation = player_transform.translation;
-        let player_xy = Vec2::new(player_translation.x, player_translation.y);
-
-        for (entity, item, item_transform) in &item_query {
-            let item_translation = item_transform.translation;
-            let item_xy = Vec2::new(item_translation.x, item_translation.y);
-
-            if player_xy.distance(item_xy) <= 22.5 / 256. {
-                match item {
-                    Item::SelfEffect(e) => {
-                        player.add_effect(e.clone());
-                    }
-                    Item::OthersEffect(e) => {
-                        others_effects.push((player.name.clone(), e.clone()));
-                    }
-                    Item::GlobalEffect(e) => match e {
-                        ItemEffectGlobal::Clear => {
-                            let texture_handle = &trail_texture.image_handle;
-                            let texture = images.get_mut(texture_handle).unwrap();
-                            let pixel_count = (texture.size().x * texture.size().y) as usize;
-                            for i in 0..pixel_count * 4 {
-                                texture.data[i] = 0;
-                            }
-                        }
-                        ItemEffectGlobal::MoreItems => {
-                            spawn_item(&mut commands, &mut meshes, &mut materials);
-                            spawn_item(&mut commands, &mut meshes, &mut materials);
-                            spawn_item(&mut commands, &mut meshes, &mut materials);
-                        }
-                    },
-                }
-
-                commands.entity(entity).despawn_recursive();
-            }
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        commands.set_state(AppState::MainMenu);
+    }
+    for (key, brush) in [
+        (KeyCode::Digit1, MapPixel::Wall),
+        (KeyCode::Digit2, MapPixel::Empty),
+        (KeyCode::Digit3, MapPixel::SpawnZone),
+        (KeyCode::Digit4, MapPixel::ItemZone),
+    ] {
+        if keyboard_input.just_pressed(key) {
+            editor.brush = brush;
         }
     }
-    for (name, effect) in others_effects {
-        for (mut player, _) in &mut player_query {
-            if player.name == name {
-                continue;
+    if keyboard_input.just_pressed(KeyCode::BracketLeft) && editor.brush_radius > 0 {
+        editor.brush_radius -= 1;
+    }
+    if keyboard_input.just_pressed(KeyCode::BracketRight) {
+        editor.brush_radius += 1;
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyS) {
+        match editor.map.save() {
+            Ok(()) => {
+                info!("map saved to {}", editor.map.path.display());
+                settings.map = Some(editor.map.path.clone());
             }
+            Err(e) => warn!("could not save map: {}", e),
+        }
+    }
 
-            player.add_effect(effect);
+    let brush = if mouse_input.pressed(MouseButton::Left) {
+        Some(editor.brush)
+    } else if mouse_input.pressed(MouseButton::Right) {
+        Some(MapPixel::Empty)
+    } else {
+        None
+    };
+    let cursor = windows
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position());
+    if let (Some(brush), Some(cursor), Ok((camera, camera_transform))) =
+        (brush, cursor, cameras.get_single())
+    {
+        if let Ok(position) = camera.viewport_to_world_2d(camera_transform, cursor) {
+            let position = position.extend(0.);
+            let x = game_to_texture_vec(position, editor.map.width() as usize).x;
+            let y = game_to_texture_vec(position, editor.map.height() as usize).y;
+            let radius = editor.brush_radius;
+            editor
+                .map
+                .paint(x.floor() as i64, y.floor() as i64, radius, brush);
+            if let Some(texture) = images.get_mut(&editor.image_handle) {
+                texture.data.copy_from_slice(editor.map.data());
+            }
         }
     }
+
+    if let Ok(mut text) = query.get_single_mut() {
+        text.0 = format!(
+            "{}  brush: {} ({} px)\n1: wall  2: erase  3: spawn zone  4: item zone  [ ]: size  S: save  Esc: back",
+            editor.map.name(),
+            editor.brush.get_text(),
+            editor.brush_radius
+        );
+    }
 }
@@ -0,0 +1,268 @@
+use bevy_math::Vec2;
+use image::{Rgba, RgbaImage};
+use rand::Rng;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Pixels of this colour mark where players may start. They are not walls.
+pub const SPAWN_ZONE_COLOR: [u8; 3] = [255, 0, 255];
+/// Pixels of this colour mark where items may appear. They are not walls.
+pub const ITEM_ZONE_COLOR: [u8; 3] = [0, 255, 255];
+/// Colour of walls painted in the map editor, and of every wall in the
+/// arena, whatever its colour in the image. No player colour matches it, so
+/// walls never count as anybody's trail.
+pub const WALL_COLOR: [u8; 3] = [200, 200, 200];
+
+/// Area of a map made of pixels of one kind.
+#[derive(Clone, Default)]
+pub struct Zone {
+    /// Game coordinates of the pixels' centres.
+    points: Vec<Vec2>,
+    /// Size of a pixel in game units.
+    pixel_size: Vec2,
+}
+
+impl Zone {
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// A uniformly distributed point of the zone, if it is not empty.
+    pub fn random_point(&self, rng: &mut impl Rng) -> Option<Vec2> {
+        if self.points.is_empty() {
+            return None;
+        }
+        let centre = self.points[rng.gen_range(0..self.points.len())];
+        let offset = Vec2::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5));
+        Some(centre + offset * self.pixel_size)
+    }
+}
+
+/// What a pixel of a map stands for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MapPixel {
+    Empty,
+    Wall,
+    SpawnZone,
+    ItemZone,
+}
+
+impl MapPixel {
+    fn of(pixel: &Rgba<u8>) -> Self {
+        if pixel[3] < 128 {
+            return MapPixel::Empty;
+        }
+        match [pixel[0], pixel[1], pixel[2]] {
+            SPAWN_ZONE_COLOR => MapPixel::SpawnZone,
+            ITEM_ZONE_COLOR => MapPixel::ItemZone,
+            _ => MapPixel::Wall,
+        }
+    }
+
+    fn color(&self) -> Rgba<u8> {
+        let [r, g, b] = match self {
+            MapPixel::Empty => return Rgba([0, 0, 0, 0]),
+            MapPixel::Wall => WALL_COLOR,
+            MapPixel::SpawnZone => SPAWN_ZONE_COLOR,
+            MapPixel::ItemZone => ITEM_ZONE_COLOR,
+        };
+        Rgba([r, g, b, 255])
+    }
+
+    pub fn get_text(&self) -> String {
+        match self {
+            MapPixel::Empty => "erase",
+            MapPixel::Wall => "wall",
+            MapPixel::SpawnZone => "spawn zone",
+            MapPixel::ItemZone => "item zone",
+        }
+        .to_string()
+    }
+}
+
+/// Failure to read or write a map image.
+#[derive(Debug)]
+pub enum MapError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::Io(e) => write!(f, "{}", e),
+            MapError::Image(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+impl From<std::io::Error> for MapError {
+    fn from(e: std::io::Error) -> Self {
+        MapError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for MapError {
+    fn from(e: image::ImageError) -> Self {
+        MapError::Image(e)
+    }
+}
+
+/// Static obstacles and spawn zones read from an image covering the whole
+/// arena. Opaque pixels are walls, except for those in `SPAWN_ZONE_COLOR`
+/// and `ITEM_ZONE_COLOR`.
+pub struct Map {
+    pub path: PathBuf,
+    image: RgbaImage,
+}
+
+impl Map {
+    /// An empty map of `size` by `size` pixels, to be saved at `path`.
+    pub fn new(path: PathBuf, size: u32) -> Self {
+        Map {
+            path,
+            image: RgbaImage::new(size, size),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MapError> {
+        let image = image::open(path)?.to_rgba8();
+        Ok(Map {
+            path: path.to_path_buf(),
+            image,
+        })
+    }
+
+    pub fn save(&self) -> Result<(), MapError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        self.image.save(&self.path)?;
+        Ok(())
+    }
+
+    pub fn width(&self) -> u32 {
+        self.image.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.image.height()
+    }
+
+    /// RGBA pixels, row by row from the top.
+    pub fn data(&self) -> &[u8] {
+        self.image.as_raw()
+    }
+
+    /// Sets all pixels within `radius` pixels of `(x, y)`.
+    pub fn paint(&mut self, x: i64, y: i64, radius: i64, pixel: MapPixel) {
+        let color = pixel.color();
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let (px, py) = (x + dx, y + dy);
+                if dx * dx + dy * dy > radius * radius
+                    || px < 0
+                    || py < 0
+                    || px >= self.width() as i64
+                    || py >= self.height() as i64
+                {
+                    continue;
+                }
+                self.image.put_pixel(px as u32, py as u32, color);
+            }
+        }
+    }
+
+    /// Name shown in the map picker.
+    pub fn name(&self) -> String {
+        map_name(&self.path)
+    }
+
+    /// The walls scaled to an arena texture of `size` by `size` pixels, as
+    /// RGBA data in `WALL_COLOR`.
+    pub fn walls(&self, size: usize) -> Vec<u8> {
+        let (width, height) = self.image.dimensions();
+        let mut data = vec![0; size * size * 4];
+        for y in 0..size {
+            for x in 0..size {
+                let map_x = (x * width as usize / size) as u32;
+                let map_y = (y * height as usize / size) as u32;
+                let pixel = self.image.get_pixel(map_x, map_y);
+                if MapPixel::of(pixel) == MapPixel::Wall {
+                    let index = (y * size + x) * 4;
+                    data[index..index + 4].copy_from_slice(&MapPixel::Wall.color().0);
+                }
+            }
+        }
+        data
+    }
+
+    /// Where players may start.
+    pub fn spawn_zone(&self) -> Zone {
+        self.zone(MapPixel::SpawnZone)
+    }
+
+    /// Where items may appear.
+    pub fn item_zone(&self) -> Zone {
+        self.zone(MapPixel::ItemZone)
+    }
+
+    fn zone(&self, kind: MapPixel) -> Zone {
+        let (width, height) = self.image.dimensions();
+        let pixel_size = Vec2::new(2. / width as f32, 2. / height as f32);
+        let points = self
+            .image
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| MapPixel::of(pixel) == kind)
+            .map(|(x, y, _)| {
+                Vec2::new(
+                    -1. + (x as f32 + 0.5) * pixel_size.x,
+                    1. - (y as f32 + 0.5) * pixel_size.y,
+                )
+            })
+            .collect();
+        Zone { points, pixel_size }
+    }
+}
+
+pub fn map_name(path: &Path) -> String {
+    path.file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or("?")
+        .to_string()
+}
+
+/// PNG files in `dir`, sorted by name.
+pub fn list_maps(dir: &Path) -> Result<Vec<PathBuf>, MapError> {
+    let mut maps = Vec::new();
+    if !dir.exists() {
+        return Ok(maps);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "png") {
+            maps.push(path);
+        }
+    }
+    maps.sort();
+    Ok(maps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walls_do_not_keep_the_colour_of_the_image() {
+        let mut map = Map::new(PathBuf::from("test.png"), 2);
+        // A wall drawn in a player's red.
+        map.image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        let walls = map.walls(2);
+        let [r, g, b] = WALL_COLOR;
+        assert_eq!(walls[..4], [r, g, b, 255]);
+        assert_eq!(walls[4..], [0; 12]);
+    }
+}
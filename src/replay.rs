@@ -0,0 +1,189 @@
+use crate::config::{self, ConfigError};
+use crate::map::{Map, MapError};
+use crate::rules::GameRules;
+use crate::simulation::{Simulation, SteerInput};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Everything needed to play a round again tick by tick: the settings the
+/// round was created with and the steering input of every player per tick.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Replay {
+    pub seed: u64,
+    pub arena_size: usize,
+    pub tick_rate: f64,
+    pub rules: GameRules,
+    /// Image file of the map the round was played on.
+    #[serde(default)]
+    pub map: Option<PathBuf>,
+    pub players: Vec<ReplayPlayer>,
+    /// Run-length encoded inputs; players hold a key for many ticks in a row.
+    inputs: Vec<InputRun>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReplayPlayer {
+    pub name: String,
+    /// sRGBA
+    pub color: [u8; 4],
+    pub team: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct InputRun {
+    ticks: u32,
+    inputs: Vec<SteerInput>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Config(ConfigError),
+    Map(MapError),
+    NoReplays,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "{}", e),
+            ReplayError::Config(e) => write!(f, "{}", e),
+            ReplayError::Map(e) => write!(f, "map: {}", e),
+            ReplayError::NoReplays => write!(f, "no replays found"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(e: std::io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+impl From<ConfigError> for ReplayError {
+    fn from(e: ConfigError) -> Self {
+        ReplayError::Config(e)
+    }
+}
+
+impl Replay {
+    /// Starts a recording for a freshly created simulation, before its first
+    /// tick.
+    pub fn new(simulation: &Simulation, tick_rate: f64) -> Self {
+        Replay {
+            seed: simulation.seed(),
+            arena_size: simulation.arena.size(),
+            tick_rate,
+            rules: simulation.rules.clone(),
+            map: simulation.map_path().map(Path::to_path_buf),
+            players: simulation
+                .players
+                .iter()
+                .map(|player| ReplayPlayer {
+                    name: player.name.clone(),
+                    color: player.color,
+                    team: player.team,
+                })
+                .collect(),
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, inputs: &[SteerInput]) {
+        if let Some(run) = self.inputs.last_mut() {
+            if run.inputs == inputs {
+                run.ticks += 1;
+                return;
+            }
+        }
+        self.inputs.push(InputRun {
+            ticks: 1,
+            inputs: inputs.to_vec(),
+        });
+    }
+
+    pub fn tick_count(&self) -> usize {
+        self.inputs.iter().map(|run| run.ticks as usize).sum()
+    }
+
+    pub fn tick_duration(&self) -> Duration {
+        Duration::from_secs_f64(1. / self.tick_rate)
+    }
+
+    /// Inputs of all players, one entry per tick.
+    pub fn tick_inputs(&self) -> Vec<Vec<SteerInput>> {
+        self.inputs
+            .iter()
+            .flat_map(|run| std::iter::repeat_n(run.inputs.clone(), run.ticks as usize))
+            .collect()
+    }
+
+    /// Recreates the simulation as it was before the first recorded tick.
+    /// This reads the map again, which fails if it was removed.
+    pub fn simulation(&self) -> Result<Simulation, ReplayError> {
+        let mut simulation = Simulation::new(self.arena_size, self.seed, self.rules.clone());
+        if let Some(path) = &self.map {
+            simulation.set_map(&Map::load(path).map_err(ReplayError::Map)?);
+        }
+        for player in &self.players {
+            simulation.add_team_player(player.name.clone(), player.color, player.team);
+        }
+        simulation.move_players_a_bit();
+        Ok(simulation)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        Ok(config::load(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        Ok(config::save_compact(self, path)?)
+    }
+
+    /// Saves the replay as the next numbered file in `dir` and returns its
+    /// path.
+    pub fn save_numbered(&self, dir: &Path) -> Result<PathBuf, ReplayError> {
+        fs::create_dir_all(dir)?;
+        let number = numbered_replays(dir)?
+            .last()
+            .map(|(number, _)| number + 1)
+            .unwrap_or(0);
+        let path = dir.join(format!("round-{:05}.ron", number));
+        self.save(&path)?;
+        Ok(path)
+    }
+
+    pub fn load_latest(dir: &Path) -> Result<Self, ReplayError> {
+        match numbered_replays(dir)?.last() {
+            Some((_, path)) => Replay::load(path),
+            None => Err(ReplayError::NoReplays),
+        }
+    }
+}
+
+/// Replay files in `dir` sorted by their number.
+fn numbered_replays(dir: &Path) -> Result<Vec<(u32, PathBuf)>, ReplayError> {
+    let mut replays = Vec::new();
+    if !dir.exists() {
+        return Ok(replays);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let number = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("round-"))
+            .and_then(|name| name.strip_suffix(".ron"))
+            .and_then(|number| number.parse::<u32>().ok());
+        if let Some(number) = number {
+            replays.push((number, path));
+        }
+    }
+    replays.sort_by_key(|(number, _)| *number);
+    Ok(replays)
+}
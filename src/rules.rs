@@ -0,0 +1,156 @@
+use crate::items::{self, EffectRules, ItemDefinition};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Round length in territory mode if no time limit is set.
+pub const TERRITORY_TIME_LIMIT_MS: u64 = 90_000;
+
+/// Range a random duration is picked from, in milliseconds. `max` is
+/// exclusive.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct MillisRange {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl MillisRange {
+    pub fn sample(&self, rng: &mut impl Rng) -> Duration {
+        if self.max <= self.min {
+            return Duration::from_millis(self.min);
+        }
+        Duration::from_millis(rng.gen_range(self.min..self.max))
+    }
+}
+
+/// What a round is about.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum GameMode {
+    /// Outliving the others; every trail is deadly.
+    #[default]
+    Survival,
+    /// Covering the largest part of the arena in one's colour before the
+    /// time runs out; only walls and the edges are deadly.
+    Territory,
+    /// Training without an end: collisions are only counted, items can be
+    /// placed at will and time runs slower.
+    Practice,
+}
+
+impl GameMode {
+    pub fn get_text(&self) -> String {
+        match self {
+            GameMode::Survival => "survival",
+            GameMode::Territory => "territory",
+            GameMode::Practice => "practice",
+        }
+        .to_string()
+    }
+}
+
+/// How steering input turns a player.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Steering {
+    /// Turning continuously at `GameRules::turn_rate` while steering.
+    #[default]
+    Smooth,
+    /// Turning by exactly 90° per key tap, leaving square corners.
+    RightAngle,
+}
+
+impl Steering {
+    pub fn get_text(&self) -> String {
+        match self {
+            Steering::Smooth => "smooth",
+            Steering::RightAngle => "right angles",
+        }
+        .to_string()
+    }
+}
+
+/// Numbers defining the feel of the game. Lengths are in game units; the
+/// arena spans from -1 to 1 on both axes. Every field missing from a rules
+/// file keeps its default.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct GameRules {
+    pub mode: GameMode,
+    /// Distance travelled per second without speed effects.
+    pub base_speed: f32,
+    pub steering: Steering,
+    /// Turning speed in radians per second, for smooth steering.
+    pub turn_rate: f32,
+    /// Trail half-width without thickness effects.
+    pub base_radius: f32,
+    /// Time between the end of one gap and the start of the next.
+    pub gap_interval_ms: MillisRange,
+    pub gap_duration_ms: u64,
+    pub item_spawn_interval_ms: MillisRange,
+    /// Time after which an uncollected item disappears, or `None` for items
+    /// staying until collected.
+    pub item_lifetime_ms: Option<u64>,
+    /// Time before disappearing during which an item blinks.
+    pub item_blink_ms: u64,
+    /// Number of items on the field above which no more spawn, or `None` for
+    /// no limit.
+    pub max_field_items: Option<usize>,
+    /// Radius around a new item that has to be free of deadly pixels.
+    pub item_clear_radius: f32,
+    /// Smallest distance between a new item and a living player's head.
+    pub item_head_distance: f32,
+    /// Distance between a head and an item at which it gets collected.
+    pub pickup_radius: f32,
+    /// Whether heads leaving the arena come back at the opposite edge
+    /// instead of dying.
+    pub wrap_around: bool,
+    /// Time after which a deadly border starts closing in from the edges,
+    /// or `None` for rounds without sudden death.
+    pub sudden_death_after_ms: Option<u64>,
+    /// Distance the sudden-death border moves inwards per second.
+    pub border_speed: f32,
+    /// Length of a round, after which survivors are ranked by their trail
+    /// length, or `None` for rounds lasting until one team is left.
+    /// Territory rounds always have a limit, `TERRITORY_TIME_LIMIT_MS` if
+    /// this is `None`.
+    pub time_limit_ms: Option<u64>,
+    /// Game time per real time in practice mode.
+    pub practice_speed: f32,
+    /// Items that can appear on the field.
+    pub items: Vec<ItemDefinition>,
+    /// Duration and stacking of the effects the items give.
+    pub effects: EffectRules,
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        GameRules {
+            mode: GameMode::Survival,
+            base_speed: 60. / 256.,
+            steering: Steering::Smooth,
+            turn_rate: std::f32::consts::PI / 60.0 / 0.016,
+            base_radius: 2.5 / 256.,
+            gap_interval_ms: MillisRange {
+                min: 1000,
+                max: 5000,
+            },
+            gap_duration_ms: 300,
+            item_spawn_interval_ms: MillisRange {
+                min: 3000,
+                max: 6000,
+            },
+            item_lifetime_ms: Some(15_000),
+            item_blink_ms: 3000,
+            max_field_items: Some(8),
+            item_clear_radius: 12. / 256.,
+            item_head_distance: 64. / 256.,
+            pickup_radius: 22.5 / 256.,
+            wrap_around: false,
+            sudden_death_after_ms: None,
+            border_speed: 10. / 256.,
+            time_limit_ms: None,
+            practice_speed: 0.5,
+            items: items::default_items(),
+            effects: EffectRules::default(),
+        }
+    }
+}
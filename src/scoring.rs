@@ -0,0 +1,118 @@
+/// Scores of a match played over several rounds. Every player keeps the
+/// index it has in the round's `Simulation` for the whole match. Points are
+/// won by players and summed up per team.
+pub struct Match {
+    pub target_score: u32,
+    /// The leading team has to be two points ahead of everyone else to win.
+    pub win_by_two: bool,
+    /// Team of every player, like `Player::team` in the simulation.
+    teams: Vec<usize>,
+    scores: Vec<u32>,
+    rounds_played: u32,
+}
+
+impl Match {
+    pub fn new(teams: Vec<usize>, target_score: u32, win_by_two: bool) -> Self {
+        Match {
+            target_score,
+            win_by_two,
+            scores: vec![0; teams.len()],
+            teams,
+            rounds_played: 0,
+        }
+    }
+
+    /// Scores per player.
+    pub fn scores(&self) -> &[u32] {
+        &self.scores
+    }
+
+    pub fn rounds_played(&self) -> u32 {
+        self.rounds_played
+    }
+
+    /// Seed of the next round in a match started with `seed`. The first
+    /// round uses `seed` itself, later ones mix in the round number, so the
+    /// rounds differ but the whole match can be played again.
+    pub fn round_seed(&self, seed: u64) -> u64 {
+        if self.rounds_played == 0 {
+            return seed;
+        }
+        // SplitMix64, so neighbouring rounds get unrelated seeds.
+        let mut z =
+            seed.wrapping_add((self.rounds_played as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Teams taking part in the match, in ascending order.
+    pub fn teams(&self) -> Vec<usize> {
+        let mut teams = self.teams.clone();
+        teams.sort();
+        teams.dedup();
+        teams
+    }
+
+    /// Indices of the players in `team`.
+    pub fn members(&self, team: usize) -> Vec<usize> {
+        (0..self.teams.len())
+            .filter(|index| self.teams[*index] == team)
+            .collect()
+    }
+
+    pub fn team_score(&self, team: usize) -> u32 {
+        self.members(team)
+            .iter()
+            .map(|index| self.scores[*index])
+            .sum()
+    }
+
+    pub fn add_round(&mut self, points: &[u32]) {
+        for (score, points) in self.scores.iter_mut().zip(points) {
+            *score += points;
+        }
+        self.rounds_played += 1;
+    }
+
+    /// Teams ordered from the highest to the lowest score.
+    pub fn standings(&self) -> Vec<usize> {
+        let mut standings = self.teams();
+        standings.sort_by_key(|team| std::cmp::Reverse(self.team_score(*team)));
+        standings
+    }
+
+    /// The team that won the match, if any.
+    pub fn winner(&self) -> Option<usize> {
+        let standings = self.standings();
+        let leader = *standings.first()?;
+        let leader_score = self.team_score(leader);
+        if leader_score < self.target_score {
+            return None;
+        }
+        let runner_up_score = standings.get(1).map(|team| self.team_score(*team));
+        let margin = if self.win_by_two { 2 } else { 1 };
+        match runner_up_score {
+            Some(score) if leader_score < score + margin => None,
+            _ => Some(leader),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn win_by_two_needs_a_two_point_lead() {
+        let mut current_match = Match::new(vec![0, 1], 3, true);
+        current_match.add_round(&[3, 2]);
+        assert_eq!(current_match.winner(), None);
+        current_match.add_round(&[1, 0]);
+        assert_eq!(current_match.winner(), Some(0));
+
+        let mut current_match = Match::new(vec![0, 1], 3, false);
+        current_match.add_round(&[3, 2]);
+        assert_eq!(current_match.winner(), Some(0));
+    }
+}
@@ -0,0 +1,1547 @@
+use crate::items::{self, EffectKind, ItemDefinition, ItemTarget};
+use crate::map::{Map, Zone};
+use crate::rules::{GameMode, GameRules, Steering, TERRITORY_TIME_LIMIT_MS};
+use bevy_math::{Quat, Vec2, Vec3};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// sRGBA colour of the sudden-death border closing in from the edges.
+const BORDER_COLOR: [u8; 4] = [140, 140, 153, 255];
+
+/// Solo survival points per collected item; every second alive is worth one.
+const POINTS_PER_ITEM: u32 = 5;
+
+/// Attempts to find a start position that is not inside a wall.
+const MAX_SPAWN_TRIES: usize = 100;
+
+/// Attempts to find a clear spot for an item before it is not spawned.
+const MAX_ITEM_SPAWN_TRIES: usize = 50;
+
+/// How long a head flashes after a collision in practice mode.
+pub const COLLISION_FLASH: Duration = Duration::from_millis(300);
+
+/// Left/right steering state of a single player for one simulation tick.
+/// Each side goes from 0 (not steering) to 1 (turning at the full rate);
+/// digital inputs only use the two extremes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SteerInput {
+    pub left: f32,
+    pub right: f32,
+}
+
+impl SteerInput {
+    pub fn digital(left: bool, right: bool) -> Self {
+        SteerInput {
+            left: if left { 1. } else { 0. },
+            right: if right { 1. } else { 0. },
+        }
+    }
+}
+
+/// Square RGBA bitmap holding everything that has been drawn into the arena.
+/// A pixel with non-zero alpha is deadly.
+pub struct Arena {
+    size: usize,
+    data: Vec<u8>,
+    /// Content `clear` resets the arena to, like the walls of a map.
+    base: Option<Vec<u8>>,
+    /// Whether leaving one edge enters the arena again at the opposite one.
+    wrap_around: bool,
+}
+
+impl Arena {
+    pub fn new(size: usize, wrap_around: bool) -> Self {
+        Arena {
+            size,
+            data: vec![0x00; size * size * 4],
+            base: None,
+            wrap_around,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn wraps_around(&self) -> bool {
+        self.wrap_around
+    }
+
+    /// Texture coordinate under `game_coord`. Outside of the arena this is
+    /// the pixel on the opposite side if the arena wraps around and `None`
+    /// otherwise.
+    pub fn texture_coord(&self, game_coord: Vec3) -> Option<(usize, usize)> {
+        if self.wrap_around {
+            game_to_texture_coord(wrap_position(game_coord), self.size)
+        } else {
+            game_to_texture_coord(game_coord, self.size)
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn alpha_at(&self, x: usize, y: usize) -> u8 {
+        self.data[(y * self.size + x) * 4 + 3]
+    }
+
+    /// Whether the pixel kills players touching it: it is not empty and not
+    /// in one of the `harmless` RGBA colours.
+    pub fn is_deadly(&self, x: usize, y: usize, harmless: &[[u8; 4]]) -> bool {
+        let index = (y * self.size + x) * 4;
+        let pixel = &self.data[index..index + 4];
+        pixel[3] != 0 && !harmless.iter().any(|color| color == pixel)
+    }
+
+    /// Whether no pixel within `radius` of `center` is deadly or outside of
+    /// the arena.
+    pub fn is_clear(&self, center: Vec3, radius: f32, harmless: &[[u8; 4]]) -> bool {
+        let pixel_size = 2. / self.size as f32;
+        let steps = (radius / pixel_size).ceil() as i64;
+        for dy in -steps..=steps {
+            for dx in -steps..=steps {
+                let offset = Vec3::new(dx as f32, dy as f32, 0.) * pixel_size;
+                if offset.length() > radius {
+                    continue;
+                }
+                match self.texture_coord(center + offset) {
+                    Some((x, y)) if !self.is_deadly(x, y, harmless) => {}
+                    _ => return false,
+                }
+            }
+        }
+        true
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: [u8; 4]) {
+        let index = (y * self.size + x) * 4; // RGBA
+        self.data[index..index + 4].copy_from_slice(&color);
+    }
+
+    /// Number of pixels showing each of the sRGBA `colors`.
+    pub fn count_colors(&self, colors: &[[u8; 4]]) -> Vec<usize> {
+        let mut counts = vec![0; colors.len()];
+        for pixel in self.data.chunks_exact(4) {
+            for (count, color) in counts.iter_mut().zip(colors) {
+                if pixel == color {
+                    *count += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    pub fn clear(&mut self) {
+        match &self.base {
+            Some(base) => self.data.copy_from_slice(base),
+            None => self.data.fill(0),
+        }
+    }
+
+    /// Replaces the arena with `data`, which stays when the arena is
+    /// cleared.
+    pub fn set_base(&mut self, data: Vec<u8>) {
+        self.data.copy_from_slice(&data);
+        self.base = Some(data);
+    }
+
+    /// Fills the pixels that are at least `from` and less than `to` pixels
+    /// away from the nearest edge.
+    pub fn fill_border(&mut self, from: usize, to: usize, color: [u8; 4]) {
+        let to = to.min(self.size.div_ceil(2));
+        for ring in from..to {
+            let far = self.size - 1 - ring;
+            for i in ring..=far {
+                self.set_pixel(i, ring, color);
+                self.set_pixel(i, far, color);
+                self.set_pixel(ring, i, color);
+                self.set_pixel(far, i, color);
+            }
+        }
+    }
+}
+
+pub struct Player {
+    pub name: String,
+    pub position: Vec3,
+    /// Position before the most recent tick, used to interpolate rendering.
+    pub previous_position: Vec3,
+    pub dir: Vec3,
+    /// sRGBA bytes the trail is drawn with.
+    pub color: [u8; 4],
+    /// Players of the same team win together and do not score for each
+    /// other's deaths.
+    pub team: usize,
+    pub alive: bool,
+    /// Tick in which the player died.
+    pub died_at: Option<u64>,
+    pub time_alive: Duration,
+    pub items_collected: u32,
+    /// Collisions survived in practice mode.
+    pub collisions: u32,
+    /// Whether the head touched something in the last tick, so crossing a
+    /// trail counts as one collision.
+    pub colliding: bool,
+    /// Time left of the flash after the latest collision.
+    pub collision_flash: Duration,
+    /// Input of the previous tick, to tell taps from held keys.
+    pub previous_input: SteerInput,
+    /// Where the player last turned by a right angle.
+    pub last_corner: Option<Vec3>,
+    pub gap_state: PlayerGapState,
+    /// Active effects with the time each has left.
+    pub item_effects: Vec<(EffectKind, Duration)>,
+}
+
+impl Player {
+    pub fn new(
+        name: String,
+        color: [u8; 4],
+        position: Vec3,
+        dir: Vec3,
+        team: usize,
+        rules: &GameRules,
+        rng: &mut impl Rng,
+    ) -> Self {
+        Player {
+            name,
+            position,
+            previous_position: position,
+            dir,
+            color,
+            team,
+            alive: true,
+            died_at: None,
+            time_alive: Duration::ZERO,
+            items_collected: 0,
+            collisions: 0,
+            colliding: false,
+            collision_flash: Duration::ZERO,
+            previous_input: SteerInput::default(),
+            last_corner: None,
+            gap_state: PlayerGapState::new(rules, rng),
+            item_effects: Vec::new(),
+        }
+    }
+
+    fn effect_count(&self, effect: EffectKind) -> usize {
+        self.item_effects
+            .iter()
+            .filter(|(active, _)| *active == effect)
+            .count()
+    }
+
+    /// Factor by which the speed effects scale the base speed.
+    pub fn speed_factor(&self, rules: &GameRules) -> f32 {
+        let faster = rules.effects.speed.stacking;
+        let slower = rules.effects.slowness.stacking;
+        faster.factor(self.effect_count(EffectKind::Speed))
+            / slower.factor(self.effect_count(EffectKind::Slowness))
+    }
+
+    /// Factor by which the thickness effects scale the base radius.
+    pub fn thickness_factor(&self, rules: &GameRules) -> f32 {
+        let thicker = rules.effects.thick.stacking;
+        let thinner = rules.effects.thin.stacking;
+        thicker.factor(self.effect_count(EffectKind::Thick))
+            / thinner.factor(self.effect_count(EffectKind::Thin))
+    }
+
+    pub fn radius(&self, rules: &GameRules) -> f32 {
+        rules.base_radius * self.thickness_factor(rules)
+    }
+
+    pub fn is_free_flying(&self) -> bool {
+        self.item_effects
+            .iter()
+            .any(|(effect, _)| *effect == EffectKind::FreeFlying)
+    }
+
+    pub fn is_steering_inverse(&self) -> bool {
+        self.item_effects
+            .iter()
+            .any(|(effect, _)| *effect == EffectKind::InverseSteer)
+    }
+
+    /// Turn for right-angle steering: 1 for a tap to the left, -1 for a tap
+    /// to the right and 0 otherwise.
+    fn right_angle_turn(&self, input: SteerInput) -> f32 {
+        let tapped = |now: f32, before: f32| now > 0.5 && before <= 0.5;
+        let input = SteerInput::digital(
+            tapped(input.left, self.previous_input.left),
+            tapped(input.right, self.previous_input.right),
+        );
+        let input = self.get_current_steer_input(input);
+        input.left - input.right
+    }
+
+    fn get_current_steer_input(&self, input: SteerInput) -> SteerInput {
+        if self.is_steering_inverse() {
+            SteerInput {
+                left: input.right,
+                right: input.left,
+            }
+        } else {
+            input
+        }
+    }
+
+    fn update_item_effects(&mut self, delta: Duration) {
+        for (_, time_left) in &mut self.item_effects {
+            *time_left = time_left.saturating_sub(delta);
+        }
+        self.item_effects
+            .retain(|(_, time_left)| *time_left != Duration::ZERO);
+    }
+
+    /// Gives the player the effect of `item`, lasting as long as the item
+    /// says or else as `rules.effects` declares. Once the player has as many
+    /// as the effect's stacking allows, the one closest to running out
+    /// starts over instead.
+    pub fn add_effect(&mut self, item: &ItemDefinition, rules: &GameRules) {
+        let effect = item.effect;
+        let (Some(rule), Some(duration)) =
+            (rules.effects.get(effect), item.duration(&rules.effects))
+        else {
+            return;
+        };
+        if self.effect_count(effect) < rule.stacking.limit() {
+            self.item_effects.push((effect, duration));
+        } else if let Some((_, oldest)) = self
+            .item_effects
+            .iter_mut()
+            .filter(|(active, _)| *active == effect)
+            .min_by_key(|(_, time_left)| *time_left)
+        {
+            *oldest = duration;
+        }
+    }
+}
+
+pub struct PlayerGapState {
+    pub gapping: bool,
+    /// Time until the gap starts or ends.
+    time_left: Duration,
+}
+
+impl PlayerGapState {
+    fn new(rules: &GameRules, rng: &mut impl Rng) -> Self {
+        Self {
+            gapping: false,
+            time_left: rules.gap_interval_ms.sample(rng),
+        }
+    }
+
+    fn update(&mut self, delta: Duration, rules: &GameRules, rng: &mut impl Rng) {
+        self.time_left = self.time_left.saturating_sub(delta);
+        if self.time_left.is_zero() {
+            if self.gapping {
+                self.gapping = false;
+                self.time_left = rules.gap_interval_ms.sample(rng);
+            } else {
+                self.gapping = true;
+                self.time_left = Duration::from_millis(rules.gap_duration_ms);
+            }
+        }
+    }
+}
+
+/// An item lying on the field. The id stays the same for the item's lifetime,
+/// so frontends can match it with whatever they use to display it.
+pub struct FieldItem {
+    pub id: u64,
+    pub item: ItemDefinition,
+    pub position: Vec3,
+    /// Time until the item disappears, if it has a limited lifetime.
+    pub time_left: Option<Duration>,
+}
+
+impl FieldItem {
+    /// Whether the item is about to disappear and should blink.
+    pub fn is_expiring(&self, rules: &GameRules) -> bool {
+        self.time_left
+            .is_some_and(|time_left| time_left <= Duration::from_millis(rules.item_blink_ms))
+    }
+}
+
+struct ItemSpawnState {
+    time_to_next_spawn: Duration,
+}
+
+impl ItemSpawnState {
+    fn new(rules: &GameRules, rng: &mut impl Rng) -> Self {
+        ItemSpawnState {
+            time_to_next_spawn: rules.item_spawn_interval_ms.sample(rng),
+        }
+    }
+
+    /// Whether an item is due. Spawns due while the field is full are
+    /// skipped.
+    fn update(
+        &mut self,
+        delta: Duration,
+        rules: &GameRules,
+        rng: &mut impl Rng,
+        field_items: usize,
+    ) -> bool {
+        self.time_to_next_spawn = self.time_to_next_spawn.saturating_sub(delta);
+        if self.time_to_next_spawn.is_zero() {
+            self.time_to_next_spawn = rules.item_spawn_interval_ms.sample(rng);
+            ItemSpawnState::has_room(rules, field_items)
+        } else {
+            false
+        }
+    }
+
+    /// Whether `max_field_items` allows another item next to `field_items`.
+    fn has_room(rules: &GameRules, field_items: usize) -> bool {
+        rules.max_field_items.is_none_or(|max| field_items < max)
+    }
+
+    fn random_position(rng: &mut impl Rng) -> Vec3 {
+        Vec3::new(rng.gen_range(-0.8..0.8), rng.gen_range(-0.8..0.8), -3.)
+    }
+}
+
+/// Complete state of one round: the arena bitmap, all players and the items
+/// on the field. It knows nothing about windows, input devices or rendering;
+/// it is advanced by feeding it one `SteerInput` per player and tick.
+///
+/// Every random decision is drawn from a single RNG seeded in `new`, so the
+/// same seed and the same inputs always produce the same round.
+pub struct Simulation {
+    pub rules: GameRules,
+    pub arena: Arena,
+    pub players: Vec<Player>,
+    pub items: Vec<FieldItem>,
+    item_spawn_state: ItemSpawnState,
+    next_item_id: u64,
+    ticks: u64,
+    /// Game time simulated so far.
+    elapsed: Duration,
+    /// Width in pixels of the sudden-death border painted into the arena.
+    border_pixels: usize,
+    map: Option<PathBuf>,
+    /// Where players start, empty if they may start anywhere.
+    spawn_zone: Zone,
+    /// Where items appear, empty if they may appear anywhere.
+    item_zone: Zone,
+    seed: u64,
+    rng: StdRng,
+}
+
+impl Simulation {
+    pub fn new(arena_size: usize, seed: u64, rules: GameRules) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        Simulation {
+            arena: Arena::new(arena_size, rules.wrap_around),
+            players: Vec::new(),
+            items: Vec::new(),
+            item_spawn_state: ItemSpawnState::new(&rules, &mut rng),
+            rules,
+            next_item_id: 0,
+            ticks: 0,
+            elapsed: Duration::ZERO,
+            border_pixels: 0,
+            map: None,
+            spawn_zone: Zone::default(),
+            item_zone: Zone::default(),
+            seed,
+            rng,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Stamps the walls of `map` into the arena and limits where players
+    /// start and items appear to its zones. Has to happen before players are
+    /// added.
+    pub fn set_map(&mut self, map: &Map) {
+        self.arena.set_base(map.walls(self.arena.size()));
+        self.spawn_zone = map.spawn_zone();
+        self.item_zone = map.item_zone();
+        self.map = Some(map.path.clone());
+    }
+
+    pub fn map_path(&self) -> Option<&Path> {
+        self.map.as_deref()
+    }
+
+    /// Places a new player at a random position and returns its index, which
+    /// is also the index of its input in `tick`. The player forms a team of
+    /// its own, numbered like its index.
+    pub fn add_player(&mut self, name: String, color: [u8; 4]) -> usize {
+        let team = self.players.len();
+        self.add_team_player(name, color, team)
+    }
+
+    /// Like `add_player`, but the player joins `team`.
+    pub fn add_team_player(&mut self, name: String, color: [u8; 4], team: usize) -> usize {
+        let (position, mut direction) =
+            random_position_and_direction(&self.spawn_zone, &self.arena, &mut self.rng);
+        if self.rules.steering == Steering::RightAngle {
+            // Start along an axis, so corners line up with the pixels.
+            direction = if direction.x.abs() > direction.y.abs() {
+                Vec3::new(direction.x.signum(), 0., 0.)
+            } else {
+                Vec3::new(0., direction.y.signum(), 0.)
+            };
+        }
+        self.players.push(Player::new(
+            name,
+            color,
+            position,
+            direction,
+            team,
+            &self.rules,
+            &mut self.rng,
+        ));
+        self.players.len() - 1
+    }
+
+    /// Draws a short piece of trail behind every player, so the starting
+    /// directions are visible before the round begins.
+    pub fn move_players_a_bit(&mut self) {
+        for player in &self.players {
+            let pos = player.position;
+            let pos_before = pos - player.dir * 4. * self.rules.base_radius;
+
+            draw_trail(
+                pos_before,
+                player.dir,
+                pos,
+                player.dir,
+                self.rules.base_radius,
+                &mut self.arena,
+                player.color,
+            );
+        }
+    }
+
+    /// Advances the round by `delta`, scaled by `practice_speed` in practice
+    /// mode. `inputs` is indexed like `players`; missing entries count as not
+    /// steering. Once the round is over this does nothing, so ticks caught up
+    /// in the same frame cannot change the result.
+    pub fn tick(&mut self, inputs: &[SteerInput], delta: Duration) {
+        if self.is_round_over() {
+            return;
+        }
+        let delta = match self.rules.mode {
+            GameMode::Practice => delta.mul_f32(self.rules.practice_speed),
+            _ => delta,
+        };
+        self.expire_items(delta);
+        if self
+            .item_spawn_state
+            .update(delta, &self.rules, &mut self.rng, self.items.len())
+        {
+            self.spawn_item();
+        }
+
+        for player in &mut self.players {
+            player.update_item_effects(delta);
+        }
+
+        self.update_border();
+
+        let harmless = self.harmless_colors();
+        for (index, player) in self.players.iter_mut().enumerate() {
+            let input = inputs.get(index).copied().unwrap_or_default();
+            move_player(
+                player,
+                input,
+                delta,
+                &self.rules,
+                &harmless,
+                &mut self.arena,
+                &mut self.rng,
+            );
+            if !player.alive && player.died_at.is_none() {
+                player.died_at = Some(self.ticks);
+            }
+            if player.alive {
+                player.time_alive += delta;
+            }
+        }
+
+        self.item_collection();
+        self.ticks += 1;
+        self.elapsed += delta;
+    }
+
+    /// Number of ticks simulated so far.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Time until the round's time limit runs out, if it has one.
+    pub fn time_left(&self) -> Option<Duration> {
+        let limit = match self.rules.mode {
+            GameMode::Survival => self.rules.time_limit_ms,
+            GameMode::Territory => {
+                Some(self.rules.time_limit_ms.unwrap_or(TERRITORY_TIME_LIMIT_MS))
+            }
+            GameMode::Practice => None,
+        };
+        limit.map(|limit| Duration::from_millis(limit).saturating_sub(self.elapsed))
+    }
+
+    /// RGBA colours of arena pixels that players can cross safely: the
+    /// trails of all players in territory mode, none otherwise.
+    pub fn harmless_colors(&self) -> Vec<[u8; 4]> {
+        match self.rules.mode {
+            GameMode::Survival | GameMode::Practice => Vec::new(),
+            GameMode::Territory => self.players.iter().map(|player| player.color).collect(),
+        }
+    }
+
+    /// Share of the arena covered by every player's colour, from 0 to 1.
+    pub fn territory_shares(&self) -> Vec<f32> {
+        let total = (self.arena.size() * self.arena.size()) as f32;
+        self.trail_pixels()
+            .iter()
+            .map(|pixels| *pixels as f32 / total)
+            .collect()
+    }
+
+    /// Points per player for this round, depending on the game mode.
+    pub fn round_points(&self) -> Vec<u32> {
+        match self.rules.mode {
+            GameMode::Survival => self.survival_points(),
+            GameMode::Territory => self.territory_points(),
+            GameMode::Practice => vec![0; self.players.len()],
+        }
+    }
+
+    pub fn is_time_up(&self) -> bool {
+        self.time_left() == Some(Duration::ZERO)
+    }
+
+    /// Number of arena pixels in every player's colour, which is how much of
+    /// their trail is still visible.
+    pub fn trail_pixels(&self) -> Vec<usize> {
+        let colors: Vec<[u8; 4]> = self.players.iter().map(|player| player.color).collect();
+        self.arena.count_colors(&colors)
+    }
+
+    /// Team that won the round: the last one standing, or once the time is
+    /// up, the one of the survivor with the most trail.
+    pub fn round_winner(&self) -> Option<usize> {
+        match self.rules.mode {
+            GameMode::Survival => {}
+            GameMode::Territory => return self.territory_winner(),
+            GameMode::Practice => return None,
+        }
+        if let [team] = self.alive_teams()[..] {
+            return Some(team);
+        }
+        if !self.is_time_up() {
+            return None;
+        }
+        let trail_pixels = self.trail_pixels();
+        let survivors: Vec<usize> = (0..self.players.len())
+            .filter(|index| self.players[*index].alive)
+            .collect();
+        let most = survivors.iter().map(|index| trail_pixels[*index]).max()?;
+        let mut leaders = survivors
+            .iter()
+            .filter(|index| trail_pixels[**index] == most)
+            .map(|index| self.players[*index].team);
+        let team = leaders.next()?;
+        leaders.all(|other| other == team).then_some(team)
+    }
+
+    /// Grows the sudden-death border to its current width. The border is
+    /// part of the arena, so running into it kills like any trail.
+    fn update_border(&mut self) {
+        let Some(after) = self.rules.sudden_death_after_ms else {
+            return;
+        };
+        let Some(time) = self.elapsed.checked_sub(Duration::from_millis(after)) else {
+            return;
+        };
+        let size = self.arena.size();
+        let inset = time.as_secs_f32() * self.rules.border_speed;
+        let pixels = (inset * size as f32 / 2.) as usize;
+        if pixels > self.border_pixels {
+            self.arena
+                .fill_border(self.border_pixels, pixels, BORDER_COLOR);
+            self.border_pixels = pixels;
+        }
+    }
+
+    pub fn alive_count(&self) -> usize {
+        self.players.iter().filter(|player| player.alive).count()
+    }
+
+    /// Teams with at least one living player, in ascending order.
+    pub fn alive_teams(&self) -> Vec<usize> {
+        let mut teams: Vec<usize> = self
+            .players
+            .iter()
+            .filter(|player| player.alive)
+            .map(|player| player.team)
+            .collect();
+        teams.sort();
+        teams.dedup();
+        teams
+    }
+
+    /// Number of arena pixels in the colours of every team, as pairs of team
+    /// and pixels.
+    fn team_trail_pixels(&self) -> Vec<(usize, usize)> {
+        let mut teams: Vec<(usize, usize)> = Vec::new();
+        for (player, pixels) in self.players.iter().zip(self.trail_pixels()) {
+            match teams.iter_mut().find(|(team, _)| *team == player.team) {
+                Some((_, team_pixels)) => *team_pixels += pixels,
+                None => teams.push((player.team, pixels)),
+            }
+        }
+        teams
+    }
+
+    /// Team covering the largest part of the arena, if there is exactly
+    /// one.
+    fn territory_winner(&self) -> Option<usize> {
+        let mut teams = self.team_trail_pixels();
+        teams.sort_by_key(|(_, pixels)| std::cmp::Reverse(*pixels));
+        match teams[..] {
+            [(team, _)] => Some(team),
+            [(team, best), (_, second), ..] if best > second => Some(team),
+            _ => None,
+        }
+    }
+
+    pub fn is_round_over(&self) -> bool {
+        if self.rules.mode == GameMode::Practice {
+            // Practice until the players leave.
+            return false;
+        }
+        if self.is_time_up() {
+            return true;
+        }
+        if self.rules.mode == GameMode::Territory {
+            // Paint until the time runs out, unless nobody is left.
+            return self.alive_count() == 0;
+        }
+        if self
+            .players
+            .iter()
+            .all(|player| player.team == self.players[0].team)
+        {
+            // Nobody to win against, like in a solo round, so play until
+            // everyone is dead.
+            return self.alive_count() == 0;
+        }
+        self.alive_teams().len() <= 1
+    }
+
+    /// Points per player for the survival order of this round: one point for
+    /// every opponent that died before the player. Players dying in the same
+    /// tick get no points for each other, and teammates never score for each
+    /// other. If the time ran out, survivors with more trail count as having
+    /// outlived those with less.
+    pub fn survival_points(&self) -> Vec<u32> {
+        let trail_pixels = if self.is_time_up() {
+            self.trail_pixels()
+        } else {
+            vec![0; self.players.len()]
+        };
+        // Players outlived everyone with a smaller key.
+        let keys: Vec<(u64, usize)> = self
+            .players
+            .iter()
+            .zip(trail_pixels)
+            .map(|(player, pixels)| match player.died_at {
+                Some(died_at) => (died_at, 0),
+                None => (u64::MAX, pixels),
+            })
+            .collect();
+        (0..self.players.len())
+            .map(|index| {
+                (0..self.players.len())
+                    .filter(|other| self.players[*other].team != self.players[index].team)
+                    .filter(|other| keys[*other] < keys[index])
+                    .count() as u32
+            })
+            .collect()
+    }
+
+    /// Points per player for the painted area of this round: one point for
+    /// every opponent whose team covers less of the arena than the player's
+    /// team. Areas are summed per team like for `round_winner`, and a round
+    /// is worth as much as in survival mode.
+    pub fn territory_points(&self) -> Vec<u32> {
+        let teams = self.team_trail_pixels();
+        let covered = |team: usize| {
+            teams
+                .iter()
+                .find(|(other, _)| *other == team)
+                .map_or(0, |(_, pixels)| *pixels)
+        };
+        self.players
+            .iter()
+            .map(|player| {
+                self.players
+                    .iter()
+                    .filter(|other| other.team != player.team)
+                    .filter(|other| covered(other.team) < covered(player.team))
+                    .count() as u32
+            })
+            .collect()
+    }
+
+    /// Solo survival score of player `index`: a point per second alive and
+    /// `POINTS_PER_ITEM` per collected item.
+    pub fn solo_score(&self, index: usize) -> u32 {
+        let player = &self.players[index];
+        player.time_alive.as_secs() as u32 + player.items_collected * POINTS_PER_ITEM
+    }
+
+    /// Puts a random item of `rules.items` on the field, unless all their
+    /// weights are zero or the field is full.
+    fn spawn_item(&mut self) {
+        if !ItemSpawnState::has_room(&self.rules, self.items.len()) {
+            return;
+        }
+        if let Some(item) = items::pick(&self.rules.items, &mut self.rng) {
+            self.add_item(item);
+        }
+    }
+
+    /// Puts `item` on the field at a random clear position, unless none is
+    /// found.
+    pub fn add_item(&mut self, item: ItemDefinition) {
+        let Some(position) = self.item_position() else {
+            return;
+        };
+        self.items.push(FieldItem {
+            id: self.next_item_id,
+            item,
+            position,
+            time_left: self.rules.item_lifetime_ms.map(Duration::from_millis),
+        });
+        self.next_item_id += 1;
+    }
+
+    /// A position for a new item that players can reach: no trail or wall
+    /// within `item_clear_radius` and no living head within
+    /// `item_head_distance`. Gives up after `MAX_ITEM_SPAWN_TRIES`.
+    fn item_position(&mut self) -> Option<Vec3> {
+        let harmless = self.harmless_colors();
+        for _ in 0..MAX_ITEM_SPAWN_TRIES {
+            let position = match self.item_zone.random_point(&mut self.rng) {
+                Some(point) => point.extend(-3.),
+                None => ItemSpawnState::random_position(&mut self.rng),
+            };
+            let near_head = self.players.iter().any(|player| {
+                player.alive
+                    && player.position.truncate().distance(position.truncate())
+                        < self.rules.item_head_distance
+            });
+            if !near_head
+                && self
+                    .arena
+                    .is_clear(position, self.rules.item_clear_radius, &harmless)
+            {
+                return Some(position);
+            }
+        }
+        None
+    }
+
+    /// Counts down the lifetime of the items and removes those whose time is
+    /// up.
+    fn expire_items(&mut self, delta: Duration) {
+        for field_item in &mut self.items {
+            if let Some(time_left) = &mut field_item.time_left {
+                *time_left = time_left.saturating_sub(delta);
+            }
+        }
+        self.items
+            .retain(|field_item| field_item.time_left != Some(Duration::ZERO));
+    }
+
+    /// Removes all trails from the arena, keeping the walls of the map.
+    pub fn clear_arena(&mut self) {
+        self.arena.clear();
+        // The border stays; it gets painted again in the next tick.
+        self.border_pixels = 0;
+    }
+
+    fn item_collection(&mut self) {
+        // Effects for several players wait until everyone has collected.
+        let mut shared_effects: Vec<(usize, ItemDefinition)> = Vec::new();
+        for index in 0..self.players.len() {
+            let player_translation = self.players[index].position;
+            let player_xy = Vec2::new(player_translation.x, player_translation.y);
+
+            let mut item_index = 0;
+            while item_index < self.items.len() {
+                let item_translation = self.items[item_index].position;
+                let item_xy = Vec2::new(item_translation.x, item_translation.y);
+
+                if player_xy.distance(item_xy) > self.rules.pickup_radius {
+                    item_index += 1;
+                    continue;
+                }
+
+                self.players[index].items_collected += 1;
+                let item = self.items.remove(item_index).item;
+                match (item.effect, item.target) {
+                    (EffectKind::Clear, _) => self.clear_arena(),
+                    (EffectKind::MoreItems, _) => {
+                        self.spawn_item();
+                        self.spawn_item();
+                        self.spawn_item();
+                    }
+                    (_, ItemTarget::Collector) => {
+                        self.players[index].add_effect(&item, &self.rules);
+                    }
+                    (_, ItemTarget::Others | ItemTarget::Global) => {
+                        shared_effects.push((self.players[index].team, item));
+                    }
+                }
+            }
+        }
+        for (collector_team, item) in shared_effects {
+            for player in self.players.iter_mut() {
+                if item.target == ItemTarget::Others && player.team == collector_team {
+                    continue;
+                }
+
+                player.add_effect(&item, &self.rules);
+            }
+        }
+    }
+}
+
+fn move_player(
+    player: &mut Player,
+    input: SteerInput,
+    delta: Duration,
+    rules: &GameRules,
+    harmless: &[[u8; 4]],
+    arena: &mut Arena,
+    rng: &mut impl Rng,
+) {
+    player.previous_position = player.position;
+    if !player.alive {
+        return;
+    }
+
+    let delta_secs = delta.as_secs_f32();
+    let mut dir_before = player.dir;
+    let player_radius = player.radius(rules);
+    let drawing = !player.gap_state.gapping && !player.is_free_flying();
+    match rules.steering {
+        Steering::Smooth => {
+            let input = player.get_current_steer_input(input);
+            let turn = input.left.clamp(0., 1.) - input.right.clamp(0., 1.);
+            if turn != 0. {
+                let rotation = Quat::from_rotation_z(turn * rules.turn_rate * delta_secs);
+                player.dir = rotation.mul_vec3(player.dir);
+            }
+        }
+        Steering::RightAngle => {
+            let turn = player.right_angle_turn(input);
+            if turn != 0. {
+                // Exact instead of by quaternion, so no error builds up.
+                let dir = player.dir;
+                player.dir = Vec3::new(-dir.y * turn, dir.x * turn, 0.);
+                if drawing {
+                    draw_corner(
+                        player.position,
+                        dir,
+                        player.dir,
+                        player_radius,
+                        arena,
+                        player.color,
+                    );
+                }
+                player.last_corner = Some(player.position);
+                dir_before = player.dir;
+            }
+        }
+    }
+    player.previous_input = input;
+
+    let pos_before = player.position;
+
+    let player_base_speed = rules.base_speed;
+    let player_speed = player_base_speed * player.speed_factor(rules);
+    player.position += player.dir * delta_secs * player_speed;
+
+    // Right after a right-angle turn the side points are still above the
+    // trail leading into the corner.
+    let near_corner = player
+        .last_corner
+        .is_some_and(|corner| corner.distance(player.position) < player_radius);
+    let collision_points = get_collision_points(player.position, player.dir, player_radius);
+    let collision_points = if near_corner {
+        &collision_points[..1]
+    } else {
+        &collision_points[..]
+    };
+    let mut hit = false;
+    for vec in collision_points.iter().copied() {
+        if let Some((x, y)) = arena.texture_coord(vec) {
+            if arena.is_deadly(x, y, harmless) && !player.is_free_flying() {
+                // something was hit
+                hit = true;
+            }
+        } else {
+            // player is out of bounds
+            hit = true;
+        }
+    }
+    let practice = rules.mode == GameMode::Practice;
+    if practice {
+        player.collision_flash = player.collision_flash.saturating_sub(delta);
+        if hit && !player.colliding {
+            player.collisions += 1;
+            player.collision_flash = COLLISION_FLASH;
+        }
+        player.colliding = hit;
+    } else if hit {
+        player.alive = false;
+    }
+
+    player.gap_state.update(delta, rules, rng);
+    if !player.gap_state.gapping && !player.is_free_flying() {
+        draw_trail(
+            pos_before,
+            dir_before,
+            player.position,
+            player.dir,
+            player_radius,
+            arena,
+            player.color,
+        );
+    }
+
+    // Practising players leaving the arena come back at the opposite edge.
+    if arena.wraps_around() || practice {
+        let wrapped = wrap_position(player.position);
+        // Move the interpolation start along, so the head does not sweep
+        // across the whole arena.
+        player.previous_position += wrapped - player.position;
+        player.position = wrapped;
+    }
+}
+
+/// Start position and direction of a player. Positions are picked from the
+/// spawn zone if there is one, and never inside a wall if avoidable.
+fn random_position_and_direction(
+    spawn_zone: &Zone,
+    arena: &Arena,
+    rng: &mut impl Rng,
+) -> (Vec3, Vec3) {
+    let mut position = Vec3::ZERO;
+    for _ in 0..MAX_SPAWN_TRIES {
+        position = match spawn_zone.random_point(rng) {
+            Some(point) => point.extend(0.),
+            None => Vec3::new(rng.gen_range(-0.8..0.8), rng.gen_range(-0.8..0.8), 0.),
+        };
+        let free = arena
+            .texture_coord(position)
+            .is_some_and(|(x, y)| arena.alpha_at(x, y) == 0);
+        if free {
+            break;
+        }
+    }
+
+    let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.).normalize();
+
+    (position, direction)
+}
+
+pub fn game_to_texture_vec(game_coord: Vec3, texture_size: usize) -> Vec3 {
+    let x = (game_coord.x + 1.0) * texture_size as f32 / 2.0;
+    let y = (game_coord.y - 1.0) * texture_size as f32 / -2.0;
+
+    Vec3::new(x, y, game_coord.z)
+}
+
+pub fn game_to_texture_coord(game_coord: Vec3, texture_size: usize) -> Option<(usize, usize)> {
+    let mapped_vec = game_to_texture_vec(game_coord, texture_size);
+    let ix = mapped_vec.x as isize;
+    let iy = mapped_vec.y as isize;
+
+    if ix < 0 || ix >= texture_size as isize || iy < 0 || iy >= texture_size as isize {
+        return None;
+    }
+
+    Some((ix as usize, iy as usize))
+}
+
+/// Fills the outer square of a right-angle corner at `corner`, which the
+/// trails leading in and out of it leave open.
+fn draw_corner(
+    corner: Vec3,
+    dir_in: Vec3,
+    dir_out: Vec3,
+    radius: f32,
+    arena: &mut Arena,
+    color: [u8; 4],
+) {
+    let start = corner - dir_out * radius / 2.;
+    draw_trail(
+        start,
+        dir_in,
+        start + dir_in * radius,
+        dir_in,
+        radius / 2.,
+        arena,
+        color,
+    );
+}
+
+/// Maps a position outside of the arena to the one it corresponds to when
+/// the edges are joined.
+pub fn wrap_position(game_coord: Vec3) -> Vec3 {
+    Vec3::new(
+        (game_coord.x + 1.).rem_euclid(2.) - 1.,
+        (game_coord.y + 1.).rem_euclid(2.) - 1.,
+        game_coord.z,
+    )
+}
+
+/// Shifts by which a shape overlapping the `min..max` range on one axis
+/// has to be repeated to show up on the opposite side as well.
+fn wrap_offsets(min: f32, max: f32) -> Vec<f32> {
+    let mut offsets = vec![0.];
+    if min < -1. {
+        offsets.push(2.);
+    }
+    if max > 1. {
+        offsets.push(-2.);
+    }
+    offsets
+}
+
+fn get_collision_points(translation: Vec3, dir: Vec3, radius: f32) -> Vec<Vec3> {
+    let rotation_left = Quat::from_rotation_z(std::f32::consts::PI / 3.);
+    let rotation_right = Quat::from_rotation_z(-std::f32::consts::PI / 3.);
+    let front = translation + radius * dir;
+    let left = translation + radius * rotation_left.mul_vec3(dir);
+    let right = translation + radius * rotation_right.mul_vec3(dir);
+    vec![front, left, right]
+}
+
+pub fn draw_trail(
+    translation_before: Vec3,
+    dir_before: Vec3,
+    translation_now: Vec3,
+    dir_now: Vec3,
+    radius: f32,
+    arena: &mut Arena,
+    color: [u8; 4],
+) {
+    let size = arena.size();
+    let rotation_90deg = Quat::from_rotation_z(std::f32::consts::PI / 2.);
+
+    let dir_rot_before = rotation_90deg.mul_vec3(dir_before);
+    let left_before = translation_before + radius * dir_rot_before;
+    let right_before = translation_before - radius * dir_rot_before;
+
+    let dir_rot_now = rotation_90deg.mul_vec3(dir_now);
+    let left_now = translation_now + radius * dir_rot_now;
+    let right_now = translation_now - radius * dir_rot_now;
+
+    let corners = [left_now, right_now, left_before, right_before];
+
+    // Trails crossing the edge of a wrapping arena are drawn a second time
+    // shifted to the opposite side.
+    let (offsets_x, offsets_y) = if arena.wraps_around() {
+        let min = corners.iter().fold(Vec3::INFINITY, |a, b| a.min(*b));
+        let max = corners.iter().fold(Vec3::NEG_INFINITY, |a, b| a.max(*b));
+        (wrap_offsets(min.x, max.x), wrap_offsets(min.y, max.y))
+    } else {
+        (vec![0.], vec![0.])
+    };
+
+    for offset_x in &offsets_x {
+        for offset_y in &offsets_y {
+            let offset = Vec3::new(*offset_x, *offset_y, 0.);
+            let quad = corners.map(|corner| game_to_texture_vec(corner + offset, size));
+
+            let coords_to_draw = get_all_coordinates_in_quad(quad);
+            for (x, y) in coords_to_draw {
+                if x < size && y < size {
+                    arena.set_pixel(x, y, color);
+                }
+            }
+        }
+    }
+}
+
+fn get_all_coordinates_in_quad(quad: [Vec3; 4]) -> HashSet<(usize, usize)> {
+    let x_min = quad.iter().map(|v| v.x as usize).min().unwrap();
+    let y_min = quad.iter().map(|v| v.y as usize).min().unwrap();
+    let x_max = quad.iter().map(|v| v.x as usize).max().unwrap();
+    let y_max = quad.iter().map(|v| v.y as usize).max().unwrap();
+
+    let mut points = HashSet::new();
+
+    for x in x_min..=x_max {
+        for y in y_min..=y_max {
+            let vec = Vec3::new(x as f32, y as f32, 0.);
+            if is_point_inside_of_quad(vec, quad) {
+                points.insert((x, y));
+            }
+        }
+    }
+
+    points
+}
+
+fn is_point_inside_of_quad(p: Vec3, quad: [Vec3; 4]) -> bool {
+    let tria0 = [quad[0], quad[1], quad[2]];
+    let tria1 = [quad[1], quad[2], quad[3]];
+
+    is_point_inside_of_triangle(p, tria0) || is_point_inside_of_triangle(p, tria1)
+}
+
+fn is_point_inside_of_triangle(p: Vec3, mut triangle: [Vec3; 3]) -> bool {
+    let area = triangle_area(triangle);
+    let mut parts_area = 0.0;
+    for i in 0..triangle.len() {
+        let replaced = triangle[i];
+        triangle[i] = p;
+        parts_area += triangle_area(triangle);
+        triangle[i] = replaced;
+    }
+
+    parts_area / area < 1.05
+}
+
+fn triangle_area(triangle: [Vec3; 3]) -> f32 {
+    0.5 * (triangle[0].x * triangle[1].y
+        + triangle[1].x * triangle[2].y
+        + triangle[2].x * triangle[0].y
+        - triangle[0].y * triangle[1].x
+        - triangle[1].y * triangle[2].x
+        - triangle[2].y * triangle[0].x)
+        .abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::{EffectRule, Stacking};
+    use crate::replay::Replay;
+
+    const ARENA_SIZE: usize = 128;
+
+    fn new_round(seed: u64) -> Simulation {
+        let mut simulation = Simulation::new(ARENA_SIZE, seed, GameRules::default());
+        simulation.add_player("RED".to_string(), [255, 0, 0, 255]);
+        simulation.add_player("BLUE".to_string(), [0, 0, 255, 255]);
+        simulation.move_players_a_bit();
+        simulation
+    }
+
+    /// Steering that changes over time, so both players turn both ways.
+    fn inputs(tick: usize) -> Vec<SteerInput> {
+        vec![
+            SteerInput::digital(tick % 40 < 10, tick % 40 >= 30),
+            SteerInput::digital(tick % 25 >= 20, tick % 25 < 5),
+        ]
+    }
+
+    fn assert_same_round(a: &Simulation, b: &Simulation) {
+        assert_eq!(a.ticks(), b.ticks());
+        assert!(a.arena.data() == b.arena.data(), "arenas differ");
+        assert_eq!(a.players.len(), b.players.len());
+        for (a, b) in a.players.iter().zip(&b.players) {
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.dir, b.dir);
+            assert_eq!(a.alive, b.alive);
+            assert_eq!(a.died_at, b.died_at);
+            assert_eq!(a.items_collected, b.items_collected);
+        }
+        let item_ids = |simulation: &Simulation| {
+            simulation
+                .items
+                .iter()
+                .map(|item| (item.id, item.position))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(item_ids(a), item_ids(b));
+    }
+
+    #[test]
+    fn same_seed_and_inputs_give_the_same_round() {
+        let tick = Duration::from_millis(16);
+        let mut a = new_round(7);
+        let mut b = new_round(7);
+        for index in 0..600 {
+            a.tick(&inputs(index), tick);
+            b.tick(&inputs(index), tick);
+        }
+        assert_same_round(&a, &b);
+    }
+
+    #[test]
+    fn replay_reproduces_the_recorded_round() {
+        let mut simulation = new_round(11);
+        let mut replay = Replay::new(&simulation, 60.);
+        for index in 0..600 {
+            let inputs = inputs(index);
+            replay.record(&inputs);
+            simulation.tick(&inputs, replay.tick_duration());
+        }
+
+        let mut replayed = replay.simulation().unwrap();
+        for inputs in replay.tick_inputs() {
+            replayed.tick(&inputs, replay.tick_duration());
+        }
+        assert_same_round(&simulation, &replayed);
+    }
+
+    #[test]
+    fn ticks_after_the_deciding_death_change_nothing() {
+        let mut rules = GameRules::default();
+        rules.items.clear();
+        let mut simulation = Simulation::new(ARENA_SIZE, 0, rules);
+        simulation.add_player("A".to_string(), [255, 0, 0, 255]);
+        simulation.add_player("B".to_string(), [0, 0, 255, 255]);
+        // Both head for the right edge, A reaches it first.
+        simulation.players[0].position = Vec3::new(0.7, 0.5, 0.);
+        simulation.players[1].position = Vec3::new(0.6, -0.5, 0.);
+        for player in &mut simulation.players {
+            player.dir = Vec3::X;
+        }
+        let tick = Duration::from_millis(16);
+        while !simulation.is_round_over() {
+            simulation.tick(&[], tick);
+        }
+        let ticks = simulation.ticks();
+        assert_eq!(simulation.round_winner(), Some(1));
+        assert_eq!(simulation.round_points(), vec![0, 1]);
+
+        for _ in 0..100 {
+            simulation.tick(&[], tick);
+        }
+        assert!(simulation.players[1].alive);
+        assert_eq!(simulation.round_winner(), Some(1));
+        assert_eq!(simulation.round_points(), vec![0, 1]);
+        assert_eq!(simulation.ticks(), ticks);
+    }
+
+    fn new_player(rules: &GameRules) -> Player {
+        let mut rng = StdRng::seed_from_u64(0);
+        Player::new(
+            "P".to_string(),
+            [255; 4],
+            Vec3::ZERO,
+            Vec3::X,
+            0,
+            rules,
+            &mut rng,
+        )
+    }
+
+    fn item(effect: EffectKind) -> ItemDefinition {
+        items::default_items()
+            .into_iter()
+            .find(|item| item.effect == effect)
+            .unwrap()
+    }
+
+    #[test]
+    fn refresh_restarts_the_running_effect() {
+        let rules = GameRules::default();
+        assert_eq!(rules.effects.free_flying.stacking, Stacking::Refresh);
+        let mut player = new_player(&rules);
+        let free_flying = item(EffectKind::FreeFlying);
+        player.add_effect(&free_flying, &rules);
+        player.update_item_effects(Duration::from_millis(3000));
+        player.add_effect(&free_flying, &rules);
+        assert_eq!(
+            player.item_effects,
+            vec![(EffectKind::FreeFlying, Duration::from_millis(5000))]
+        );
+    }
+
+    #[test]
+    fn a_capped_effect_restarts_the_one_closest_to_running_out() {
+        let mut rules = GameRules::default();
+        rules.effects.speed = EffectRule {
+            duration_ms: 5000,
+            stacking: Stacking::Cap(2),
+        };
+        let mut player = new_player(&rules);
+        let speed = item(EffectKind::Speed);
+        player.add_effect(&speed, &rules);
+        player.update_item_effects(Duration::from_millis(1000));
+        player.add_effect(&speed, &rules);
+        player.update_item_effects(Duration::from_millis(1000));
+        player.add_effect(&speed, &rules);
+        let time_left: Vec<Duration> = player.item_effects.iter().map(|(_, time)| *time).collect();
+        assert_eq!(
+            time_left,
+            vec![Duration::from_millis(5000), Duration::from_millis(4000)]
+        );
+    }
+
+    #[test]
+    fn additive_speed_adds_the_base_speed_and_exponential_doubles_it() {
+        let speed = item(EffectKind::Speed);
+        for (stacking, factor) in [(Stacking::Additive, 3.), (Stacking::Exponential, 4.)] {
+            let mut rules = GameRules::default();
+            rules.effects.speed.stacking = stacking;
+            let mut player = new_player(&rules);
+            player.add_effect(&speed, &rules);
+            player.add_effect(&speed, &rules);
+            assert_eq!(player.speed_factor(&rules), factor);
+        }
+    }
+
+    #[test]
+    fn an_item_duration_overrides_the_effect_rule() {
+        let rules = GameRules::default();
+        let mut player = new_player(&rules);
+        let mut short = item(EffectKind::Thick);
+        short.duration_ms = Some(1000);
+        player.add_effect(&short, &rules);
+        player.add_effect(&item(EffectKind::Thick), &rules);
+        let time_left: Vec<Duration> = player.item_effects.iter().map(|(_, time)| *time).collect();
+        assert_eq!(
+            time_left,
+            vec![
+                Duration::from_millis(1000),
+                Duration::from_millis(rules.effects.thick.duration_ms)
+            ]
+        );
+    }
+
+    #[test]
+    fn items_disappear_when_their_lifetime_is_up() {
+        let rules = GameRules {
+            item_lifetime_ms: Some(1000),
+            ..GameRules::default()
+        };
+        let mut simulation = Simulation::new(ARENA_SIZE, 0, rules);
+        simulation.add_item(item(EffectKind::Speed));
+        assert_eq!(simulation.items.len(), 1);
+        simulation.expire_items(Duration::from_millis(600));
+        assert_eq!(
+            simulation.items[0].time_left,
+            Some(Duration::from_millis(400))
+        );
+        simulation.expire_items(Duration::from_millis(400));
+        assert!(simulation.items.is_empty());
+    }
+
+    #[test]
+    fn no_items_spawn_while_the_field_is_full() {
+        let rules = GameRules {
+            max_field_items: Some(2),
+            item_lifetime_ms: None,
+            ..GameRules::default()
+        };
+        let mut simulation = Simulation::new(ARENA_SIZE, 0, rules);
+        for _ in 0..10 {
+            simulation.spawn_item();
+        }
+        assert_eq!(simulation.items.len(), 2);
+
+        // A spawn falling due while the field is full is skipped, not saved
+        // for later.
+        let rules = &simulation.rules;
+        let rng = &mut simulation.rng;
+        let mut state = ItemSpawnState::new(rules, rng);
+        let long = Duration::from_secs(60);
+        assert!(!state.update(long, rules, rng, 2));
+        assert!(!state.update(Duration::ZERO, rules, rng, 1));
+        assert!(state.update(long, rules, rng, 1));
+    }
+
+    #[test]
+    fn items_appear_away_from_heads_and_trails() {
+        let mut simulation = Simulation::new(ARENA_SIZE, 0, GameRules::default());
+        simulation.add_player("A".to_string(), [255, 0, 0, 255]);
+        simulation.players[0].position = Vec3::new(0.4, 0., 0.);
+        // A trail covering the left half of the arena.
+        for y in 0..ARENA_SIZE {
+            for x in 0..ARENA_SIZE / 2 {
+                simulation.arena.set_pixel(x, y, [255, 0, 0, 255]);
+            }
+        }
+        let head = simulation.players[0].position.truncate();
+        let rules = simulation.rules.clone();
+        let mut found = 0;
+        for _ in 0..200 {
+            let Some(position) = simulation.item_position() else {
+                continue;
+            };
+            found += 1;
+            let (x, y) = simulation.arena.texture_coord(position).unwrap();
+            assert_eq!(simulation.arena.alpha_at(x, y), 0, "{position} on a trail");
+            assert!(
+                position.truncate().distance(head) >= rules.item_head_distance,
+                "{position} next to a head"
+            );
+        }
+        assert!(found > 0);
+    }
+
+    #[test]
+    fn survival_points_count_opponents_outlived() {
+        let mut simulation = Simulation::new(ARENA_SIZE, 0, GameRules::default());
+        for name in ["A", "B", "C", "D"] {
+            simulation.add_player(name.to_string(), [255; 4]);
+        }
+        for (player, died_at) in simulation.players.iter_mut().zip([5, 10, 10]) {
+            player.alive = false;
+            player.died_at = Some(died_at);
+        }
+        // B and C died in the same tick and get no points for each other.
+        assert_eq!(simulation.survival_points(), vec![0, 1, 1, 3]);
+    }
+
+    #[test]
+    fn territory_points_follow_the_area_of_whole_teams() {
+        let rules = GameRules {
+            mode: GameMode::Territory,
+            ..GameRules::default()
+        };
+        let mut simulation = Simulation::new(ARENA_SIZE, 0, rules);
+        let colors = [[255, 0, 0, 255], [255, 128, 128, 255], [0, 0, 255, 255]];
+        simulation.add_team_player("A1".to_string(), colors[0], 0);
+        simulation.add_team_player("A2".to_string(), colors[1], 0);
+        simulation.add_team_player("B".to_string(), colors[2], 1);
+        // A1 and A2 paint 30 pixels each, B alone paints 40.
+        for (row, (color, pixels)) in colors.into_iter().zip([30, 30, 40]).enumerate() {
+            for x in 0..pixels {
+                simulation.arena.set_pixel(x, row, color);
+            }
+        }
+        assert_eq!(simulation.round_winner(), Some(0));
+        assert_eq!(simulation.territory_points(), vec![1, 1, 0]);
+    }
+
+    #[test]
+    fn teammates_do_not_score_for_each_other() {
+        let mut simulation = Simulation::new(ARENA_SIZE, 0, GameRules::default());
+        simulation.add_team_player("A".to_string(), [255; 4], 0);
+        simulation.add_team_player("B".to_string(), [255; 4], 0);
+        simulation.add_team_player("C".to_string(), [255; 4], 1);
+        simulation.players[0].alive = false;
+        simulation.players[0].died_at = Some(3);
+        assert_eq!(simulation.survival_points(), vec![0, 0, 1]);
+    }
+}